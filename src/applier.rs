@@ -0,0 +1,141 @@
+//! The applier module is responsible for applying operations to a target database exactly once by
+//! recording the progress of the applier in the same transaction as each operation.
+
+use bson::{doc, Document};
+use mongodb::options::UpdateOptions;
+use mongodb::{bson, Client, Collection, Database};
+
+use crate::{apply, Operation, OplogBuilder, Result};
+
+/// The collection of the target database recording the progress of each applier.
+const PROGRESS: &str = "__oplog_progress";
+
+/// Applies operations to a target database, recording the timestamp of each in the same
+/// transaction so that resuming never applies an operation twice or skips one.
+///
+/// Progress is recorded as `{ _id: <id>, ts: <timestamp> }` in the `__oplog_progress` collection
+/// of the target database. Each operation is applied (see `Operation::apply_to`) and its
+/// timestamp recorded within a single transaction, so a crash either leaves both or neither.
+/// Operations at or before the recorded timestamp are skipped, so resuming with `resume` (which
+/// starts from the last applied operation) does not re-apply it.
+///
+/// Transactions require the target to be a replica set or sharded cluster. Most commands cannot
+/// run within a transaction so are applied before recording their timestamp in a transaction of
+/// its own: a crash between the two may apply a command twice.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{IdempotentApplier, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let source = Client::with_uri_str("mongodb://source").await?;
+/// let target = Client::with_uri_str("mongodb://target").await?;
+/// let applier = IdempotentApplier::new(&target, "replica");
+///
+/// let builder = applier.resume(Oplog::builder().namespace_filter("sales.*")).await?;
+/// let mut oplog = builder.build(&source).await?;
+///
+/// while let Some(res) = oplog.next().await {
+///     applier.apply(&res?).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct IdempotentApplier {
+    /// The client of the target deployment.
+    client: Client,
+    /// The database operations are applied to.
+    db: Database,
+    /// The identifier of the applier's progress in the progress collection.
+    id: String,
+}
+
+impl IdempotentApplier {
+    /// Returns an applier of operations to the given database of the target deployment.
+    pub fn new(client: &Client, database: &str) -> IdempotentApplier {
+        IdempotentApplier {
+            client: client.clone(),
+            db: client.database(database),
+            id: "oplog".into(),
+        }
+    }
+
+    /// Set the identifier under which progress is recorded.
+    ///
+    /// This defaults to `oplog` and only needs changing when several appliers, e.g. of different
+    /// sources, apply to the same database.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// Returns the timestamp of the last operation applied, if any.
+    pub async fn applied_ts(&self) -> Result<Option<bson::Timestamp>> {
+        let progress = self.progress().find_one(self.query(), None).await?;
+
+        Ok(progress.and_then(|progress| progress.get_timestamp("ts").ok()))
+    }
+
+    /// Configure a builder to start from the last operation applied, if any.
+    ///
+    /// The last operation applied is returned again by the oplog but skipped by `apply`.
+    pub async fn resume(&self, builder: OplogBuilder) -> Result<OplogBuilder> {
+        Ok(match self.applied_ts().await? {
+            Some(ts) => builder.start_at(ts),
+            None => builder,
+        })
+    }
+
+    /// Apply an operation and record its timestamp, returning whether it was applied.
+    ///
+    /// Operations at or before the last operation applied return `false` without being applied.
+    pub async fn apply(&self, operation: &Operation) -> Result<bool> {
+        let ts = operation.oplog_ts();
+        let mut session = self.client.start_session(None).await?;
+        session.start_transaction(None).await?;
+
+        let progress = self
+            .progress()
+            .find_one_with_session(self.query(), None, &mut session)
+            .await?;
+        if let Some(applied) = progress.and_then(|progress| progress.get_timestamp("ts").ok()) {
+            if applied >= ts {
+                session.abort_transaction().await?;
+
+                return Ok(false);
+            }
+        }
+
+        match *operation {
+            Operation::Command { .. } => apply::apply(operation, &self.db, None).await?,
+            _ => apply::apply(operation, &self.db, Some(&mut session)).await?,
+        }
+
+        let opts = UpdateOptions::builder().upsert(true).build();
+        self.progress()
+            .update_one_with_session(
+                self.query(),
+                doc! { "$set": { "ts": ts } },
+                opts,
+                &mut session,
+            )
+            .await?;
+        session.commit_transaction().await?;
+
+        Ok(true)
+    }
+
+    /// Returns the collection recording progress.
+    fn progress(&self) -> Collection<Document> {
+        self.db.collection(PROGRESS)
+    }
+
+    /// Returns the query matching the applier's progress.
+    fn query(&self) -> Document {
+        doc! { "_id": &self.id }
+    }
+}
@@ -0,0 +1,229 @@
+//! The apply module is responsible for re-applying operations to a target database, e.g. to
+//! replicate one deployment into another.
+//!
+//! Operations are applied idempotently where possible so that replaying part of the oplog (e.g.
+//! after resuming from an earlier timestamp) leaves the target in the same state.
+
+use bson::{doc, Bson, Document};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use mongodb::options::{ReplaceOptions, UpdateModifications};
+use mongodb::{bson, ClientSession, Collection, Database};
+
+use crate::{CommandKind, Error, Operation, Result, UpdateModification};
+
+/// Apply an operation to the given database, optionally within a session.
+///
+/// The operations contained in an `ApplyOps` are applied in order within the same session.
+pub(crate) fn apply<'a>(
+    operation: &'a Operation,
+    db: &'a Database,
+    mut session: Option<&'a mut ClientSession>,
+) -> BoxFuture<'a, Result<()>> {
+    async move {
+        match *operation {
+            Operation::Insert { ref document, .. } => {
+                let coll = collection(operation, db)?;
+
+                match document.get("_id") {
+                    Some(id) => {
+                        let query = doc! { "_id": id.clone() };
+                        let opts = ReplaceOptions::builder().upsert(true).build();
+
+                        match session {
+                            Some(session) => {
+                                coll.replace_one_with_session(query, document, opts, session)
+                                    .await?
+                            }
+                            None => coll.replace_one(query, document, opts).await?,
+                        };
+                    }
+                    None => {
+                        match session {
+                            Some(session) => {
+                                coll.insert_one_with_session(document, None, session)
+                                    .await?
+                            }
+                            None => coll.insert_one(document, None).await?,
+                        };
+                    }
+                }
+            }
+            Operation::Update {
+                ref query,
+                ref modification,
+                ..
+            } => {
+                let coll = collection(operation, db)?;
+
+                match *modification {
+                    UpdateModification::Replacement(ref document) => {
+                        let opts = ReplaceOptions::builder().upsert(true).build();
+
+                        match session {
+                            Some(session) => {
+                                coll.replace_one_with_session(
+                                    query.clone(),
+                                    document,
+                                    opts,
+                                    session,
+                                )
+                                .await?
+                            }
+                            None => coll.replace_one(query.clone(), document, opts).await?,
+                        };
+                    }
+                    ref modification => {
+                        let update = UpdateModifications::Document(operators(modification));
+
+                        match session {
+                            Some(session) => {
+                                coll.update_one_with_session(query.clone(), update, None, session)
+                                    .await?
+                            }
+                            None => coll.update_one(query.clone(), update, None).await?,
+                        };
+                    }
+                }
+            }
+            Operation::Delete { ref query, .. } => {
+                let coll = collection(operation, db)?;
+
+                match session {
+                    Some(session) => {
+                        coll.delete_one_with_session(query.clone(), None, session)
+                            .await?
+                    }
+                    None => coll.delete_one(query.clone(), None).await?,
+                };
+            }
+            Operation::Command { .. } if skipped(operation) => {}
+            Operation::Command { ref command, .. } => {
+                let command = runnable(operation).unwrap_or_else(|| command.clone());
+
+                match session {
+                    Some(session) => db.run_command_with_session(command, None, session).await?,
+                    None => db.run_command(command, None).await?,
+                };
+            }
+            Operation::ApplyOps { ref operations, .. } => {
+                for operation in operations {
+                    apply(operation, db, session.as_deref_mut()).await?;
+                }
+            }
+            Operation::Unknown { ref op, .. } => return Err(Error::UnknownOperation(op.clone())),
+            Operation::Noop { .. }
+            | Operation::CommitTransaction { .. }
+            | Operation::AbortTransaction { .. } => {}
+        }
+
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Returns the collection of the operation's namespace in the given database.
+fn collection(operation: &Operation, db: &Database) -> Result<Collection<Document>> {
+    operation
+        .collection()
+        .map(|collection| db.collection(collection))
+        .ok_or(Error::InvalidOperation)
+}
+
+/// Returns the update operators applying a modification.
+///
+/// Diffs are converted into the equivalent `$set` and `$unset` and any `$v` version marker is
+/// removed as it is not accepted by the server.
+fn operators(modification: &UpdateModification) -> Document {
+    match *modification {
+        UpdateModification::Replacement(ref document) => document.clone(),
+        UpdateModification::Operators(ref operators) => {
+            let mut operators = operators.clone();
+            operators.remove("$v");
+
+            operators
+        }
+        UpdateModification::Diff {
+            ref set,
+            ref unset,
+            ref insert,
+        } => {
+            let mut fields = set.clone();
+            fields.extend(insert.clone());
+
+            let mut operators = Document::new();
+            if !fields.is_empty() {
+                operators.insert("$set", fields);
+            }
+            if !unset.is_empty() {
+                let unset: Document = unset
+                    .iter()
+                    .map(|field| (field.clone(), Bson::String(String::new())))
+                    .collect();
+                operators.insert("$unset", unset);
+            }
+
+            operators
+        }
+    }
+}
+
+/// Returns whether a command recorded in the oplog has no equivalent to run.
+///
+/// Two-phase index builds are applied in full when committed so their start and any abort are
+/// skipped.
+fn skipped(operation: &Operation) -> bool {
+    matches!(
+        operation.command_kind(),
+        Some(CommandKind::StartIndexBuild { .. }) | Some(CommandKind::AbortIndexBuild { .. })
+    )
+}
+
+/// Returns a command which can be run in place of one recorded in the oplog in a different form.
+///
+/// Index creation is recorded with a single inline index specification rather than the `indexes`
+/// list expected by the server, and two-phase index builds as internal commands.
+fn runnable(operation: &Operation) -> Option<Document> {
+    match operation.command_kind()? {
+        CommandKind::CreateIndexes {
+            collection,
+            indexes,
+        }
+        | CommandKind::CommitIndexBuild {
+            collection,
+            indexes,
+            ..
+        } => Some(doc! { "createIndexes": collection, "indexes": indexes }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_strips_version_markers() {
+        let modification =
+            UpdateModification::Operators(doc! { "$v" : 1, "$set" : { "foo" : "bar" } });
+
+        assert_eq!(
+            operators(&modification),
+            doc! { "$set" : { "foo" : "bar" } }
+        );
+    }
+
+    #[test]
+    fn operators_converts_diffs() {
+        let modification = UpdateModification::Diff {
+            set: doc! { "a.b" : 1 },
+            unset: vec!["c".into()],
+            insert: doc! { "d" : 2 },
+        };
+
+        assert_eq!(
+            operators(&modification),
+            doc! { "$set" : { "a.b" : 1, "d" : 2 }, "$unset" : { "c" : "" } }
+        );
+    }
+}
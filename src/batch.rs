@@ -0,0 +1,204 @@
+//! The batch module is responsible for grouping a stream of operations into batches so sinks can
+//! write them together.
+
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Future, Stream, StreamExt};
+use tokio::time::Sleep;
+
+use crate::{Error, Operation, Result};
+
+/// A stream grouping the operations of another stream into batches.
+///
+/// A batch is yielded as soon as `size` operations are buffered or `timeout` has elapsed since
+/// the first of them was buffered, whichever comes first, so operations are never held back for
+/// longer than the timeout on a quiet oplog. Any partial batch is yielded when the stream ends.
+///
+/// Errors are yielded on their own after any operations buffered before them so the order of the
+/// stream is preserved.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::Oplog;
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut batches = Oplog::new(&client)
+///     .await?
+///     .batches(500, Duration::from_secs(1));
+///
+/// while let Some(res) = batches.next().await {
+///     println!("Writing {} operations", res?.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Batcher<S> {
+    /// The underlying stream of operations.
+    stream: S,
+    /// The number of operations which fill a batch.
+    size: usize,
+    /// The longest the first operation of a batch is buffered before the batch is yielded.
+    timeout: Duration,
+    /// The operations buffered for the next batch.
+    buffered: Vec<Operation>,
+    /// Wakes the stream when the timeout of the buffered batch elapses.
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// An error to yield after the buffered batch.
+    error: Option<Error>,
+    /// Whether the underlying stream has ended.
+    done: bool,
+}
+
+impl<S> Batcher<S>
+where
+    S: Stream<Item = Result<Operation>> + Unpin,
+{
+    /// Returns a stream of batches of at most `size` operations (and at least one) from the given
+    /// stream, yielding partial batches after `timeout`.
+    pub fn new(stream: S, size: usize, timeout: Duration) -> Batcher<S> {
+        let size = size.max(1);
+
+        Batcher {
+            stream,
+            size,
+            timeout,
+            buffered: Vec::with_capacity(size),
+            deadline: None,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Returns the underlying stream, discarding any buffered operations.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Yield the buffered operations as a batch.
+    fn flush(&mut self) -> Poll<Option<Result<Vec<Operation>>>> {
+        self.deadline = None;
+        let batch = mem::replace(&mut self.buffered, Vec::with_capacity(self.size));
+
+        Poll::Ready(Some(Ok(batch)))
+    }
+}
+
+impl<S> Stream for Batcher<S>
+where
+    S: Stream<Item = Result<Operation>> + Unpin,
+{
+    type Item = Result<Vec<Operation>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        while !this.done {
+            match this.stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(operation))) => {
+                    if this.buffered.is_empty() {
+                        this.deadline = Some(Box::pin(tokio::time::sleep(this.timeout)));
+                    }
+                    this.buffered.push(operation);
+
+                    if this.buffered.len() >= this.size {
+                        return this.flush();
+                    }
+                }
+                Poll::Ready(Some(Err(e))) if this.buffered.is_empty() => {
+                    return Poll::Ready(Some(Err(e)))
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    this.error = Some(e);
+
+                    return this.flush();
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        if this.buffered.is_empty() {
+            return if this.done {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            };
+        }
+
+        let elapsed = this
+            .deadline
+            .as_mut()
+            .is_none_or(|deadline| deadline.as_mut().poll(cx).is_ready());
+
+        if this.done || elapsed {
+            this.flush()
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Oplog;
+    use bson::{doc, Document};
+    use futures::stream;
+    use mongodb::bson;
+
+    fn insert(increment: u32) -> mongodb::error::Result<Document> {
+        Ok(doc! {
+            "ts": bson::Timestamp { time: 1479561394, increment },
+            "op": "i",
+            "ns": "foo.bar",
+            "o": { "_id": increment }
+        })
+    }
+
+    fn sizes(batches: &[Vec<Operation>]) -> Vec<usize> {
+        batches.iter().map(Vec::len).collect()
+    }
+
+    #[tokio::test]
+    async fn batcher_yields_full_batches_and_flushes_on_end() {
+        let oplog = Oplog::from_documents(stream::iter((0..7).map(insert)));
+
+        let batches: Vec<Vec<Operation>> = oplog
+            .batches(3, Duration::from_secs(60))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(sizes(&batches), vec![3, 3, 1]);
+        assert_eq!(batches[2][0].sort_key(), (1479561394, 6));
+    }
+
+    #[tokio::test]
+    async fn batcher_yields_partial_batches_after_the_timeout() {
+        let oplog = Oplog::from_documents(
+            stream::iter(vec![insert(0), insert(1)]).chain(stream::pending()),
+        );
+        let mut batches = oplog.batches(100, Duration::from_secs(60));
+
+        tokio::time::pause();
+        let started = tokio::time::Instant::now();
+        let batch = batches.next().await.unwrap().unwrap();
+
+        assert_eq!(batch.len(), 2);
+        // The timer has millisecond granularity so may round the deadline up.
+        assert!(started.elapsed() >= Duration::from_secs(60));
+        assert!(started.elapsed() < Duration::from_secs(61));
+    }
+}
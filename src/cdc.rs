@@ -0,0 +1,189 @@
+//! The cdc module is responsible for converting operations into normalized change records for
+//! change data capture (CDC) sinks such as relational stores, in the style of Debezium.
+
+use crate::change_event::UpdateDescription;
+use crate::{Operation, UpdateModification};
+use bson::Document;
+use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A normalized change to a row (i.e. a document) of a table (i.e. a collection).
+///
+/// This is returned by `Operation::to_cdc_record`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CdcRecord {
+    /// The type of change: `c` for a create (insert), `u` for an update and `d` for a delete.
+    pub op: char,
+    /// The database of the change.
+    pub db: String,
+    /// The collection of the change.
+    pub table: String,
+    /// The document before the change, as far as is known.
+    ///
+    /// This is the pre-image of an update or delete if known (see `Operation::pre_image`) and
+    /// otherwise only the query identifying the changed document (usually its `_id`).
+    pub before: Option<Document>,
+    /// The document after the change, as far as is known.
+    ///
+    /// This is the full document of an insert or replacement. For an update with operators or a
+    /// diff, this is only the query identifying the document together with the fields set by the
+    /// update.
+    pub after: Option<Document>,
+    /// The time of the change in milliseconds since the Unix epoch.
+    pub ts_ms: i64,
+}
+
+impl CdcRecord {
+    /// Returns the change record of an operation, if it has one.
+    pub(crate) fn new(operation: &Operation) -> Option<CdcRecord> {
+        let written = operation
+            .metadata()
+            .wall_clock
+            .unwrap_or_else(|| operation.timestamp());
+        let record = |op: char, before: Option<Document>, after: Option<Document>| {
+            Some(CdcRecord {
+                op,
+                db: operation.database()?.into(),
+                table: operation.collection()?.into(),
+                before,
+                after,
+                ts_ms: written.timestamp_millis(),
+            })
+        };
+
+        let before = operation
+            .pre_image()
+            .or_else(|| operation.document_key())
+            .cloned();
+
+        match *operation {
+            Operation::Insert { ref document, .. } => record('c', None, Some(document.clone())),
+            Operation::Update {
+                ref query,
+                ref modification,
+                ..
+            } => {
+                let after = match *modification {
+                    UpdateModification::Replacement(ref document) => document.clone(),
+                    ref modification => {
+                        let mut after = query.clone();
+                        after.extend(UpdateDescription::new(modification).updated_fields);
+
+                        after
+                    }
+                };
+
+                record('u', before, Some(after))
+            }
+            Operation::Delete { .. } => record('d', before, None),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson};
+
+    fn ts() -> Bson {
+        Bson::Timestamp(bson::Timestamp {
+            time: 1479561394,
+            increment: 1,
+        })
+    }
+
+    #[test]
+    fn cdc_record_maps_inserts() {
+        let insert = Operation::new(&doc! {
+            "ts" : ts(),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(
+            insert.to_cdc_record(),
+            Some(CdcRecord {
+                op: 'c',
+                db: "foo".into(),
+                table: "bar".into(),
+                before: None,
+                after: Some(doc! { "_id" : 1, "foo" : "bar" }),
+                ts_ms: 1479561394000,
+            })
+        );
+    }
+
+    #[test]
+    fn cdc_record_maps_deletes() {
+        let delete = Operation::new(&doc! {
+            "ts" : ts(),
+            "wall" : bson::DateTime::from_millis(1479561394500),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(
+            delete.to_cdc_record(),
+            Some(CdcRecord {
+                op: 'd',
+                db: "foo".into(),
+                table: "bar".into(),
+                before: Some(doc! { "_id" : 1 }),
+                after: None,
+                ts_ms: 1479561394500,
+            })
+        );
+    }
+
+    #[test]
+    fn cdc_record_uses_pre_images_before_deletes() {
+        let delete = Operation::new(&doc! {
+            "ts" : ts(),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 },
+            "preImage" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+        let record = delete.to_cdc_record().unwrap();
+
+        assert_eq!(record.before, Some(doc! { "_id" : 1, "foo" : "bar" }));
+    }
+
+    #[test]
+    fn cdc_record_maps_update_diffs() {
+        let update = Operation::new(&doc! {
+            "ts" : ts(),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : { "$v" : 2, "diff" : { "u" : { "foo" : "baz" }, "d" : { "qux" : false } } }
+        })
+        .unwrap();
+        let record = update.to_cdc_record().unwrap();
+
+        assert_eq!(record.op, 'u');
+        assert_eq!(record.before, Some(doc! { "_id" : 1 }));
+        assert_eq!(record.after, Some(doc! { "_id" : 1, "foo" : "baz" }));
+    }
+
+    #[test]
+    fn cdc_record_is_none_for_commands() {
+        let command = Operation::new(&doc! {
+            "ts" : ts(),
+            "op" : "c",
+            "ns" : "foo.$cmd",
+            "o" : { "drop" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(command.to_cdc_record(), None);
+    }
+}
@@ -0,0 +1,328 @@
+//! The change event module is responsible for converting operations into events shaped like those
+//! of MongoDB change streams for consumers migrating from them.
+
+use crate::{CommandKind, Operation, UpdateModification};
+use bson::{doc, Document};
+use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An event shaped like a MongoDB change stream event.
+///
+/// This is returned by `Operation::to_change_event`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChangeEvent {
+    /// The type of change, e.g. `insert`, `update`, `replace`, `delete`, `drop` or `rename`.
+    pub operation_type: String,
+    /// The namespace affected by the change.
+    pub ns: ChangeNamespace,
+    /// The new namespace of a renamed collection.
+    pub to: Option<ChangeNamespace>,
+    /// The `_id` of the document affected by the change, if any.
+    pub document_key: Option<Document>,
+    /// The full document of an insert or replacement.
+    pub full_document: Option<Document>,
+    /// The fields changed by an update.
+    pub update_description: Option<UpdateDescription>,
+    /// The raw oplog timestamp of the change.
+    pub cluster_time: bson::Timestamp,
+}
+
+/// The database and collection of a `ChangeEvent`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChangeNamespace {
+    /// The database of the namespace.
+    pub db: String,
+    /// The collection of the namespace, if any.
+    pub coll: Option<String>,
+}
+
+/// The fields changed by an update `ChangeEvent`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpdateDescription {
+    /// The fields set by the update and their new values.
+    pub updated_fields: Document,
+    /// The fields removed by the update.
+    pub removed_fields: Vec<String>,
+}
+
+impl ChangeNamespace {
+    /// Split a full namespace into its database and collection.
+    fn new(namespace: &str) -> ChangeNamespace {
+        match namespace.split_once('.') {
+            Some((db, coll)) => ChangeNamespace {
+                db: db.into(),
+                coll: Some(coll.into()),
+            },
+            None => ChangeNamespace {
+                db: namespace.into(),
+                coll: None,
+            },
+        }
+    }
+
+    /// Returns the namespace of a collection in the same database as a command namespace.
+    fn sibling(namespace: &str, collection: &str) -> ChangeNamespace {
+        ChangeNamespace {
+            coll: Some(collection.into()),
+            ..ChangeNamespace::new(namespace)
+        }
+    }
+}
+
+impl ChangeEvent {
+    /// Returns the change event of an operation, if it has one.
+    pub(crate) fn new(operation: &Operation) -> Option<ChangeEvent> {
+        let event = |operation_type: &str, ns| ChangeEvent {
+            operation_type: operation_type.into(),
+            ns,
+            to: None,
+            document_key: None,
+            full_document: None,
+            update_description: None,
+            cluster_time: operation.oplog_ts(),
+        };
+
+        match *operation {
+            Operation::Insert {
+                ref namespace,
+                ref document,
+                ..
+            } => Some(ChangeEvent {
+                document_key: document_key(document),
+                full_document: Some(document.clone()),
+                ..event("insert", ChangeNamespace::new(namespace))
+            }),
+            Operation::Update {
+                ref namespace,
+                ref query,
+                ref modification,
+                ..
+            } => {
+                let ns = ChangeNamespace::new(namespace);
+
+                Some(match *modification {
+                    UpdateModification::Replacement(ref document) => ChangeEvent {
+                        document_key: Some(query.clone()),
+                        full_document: Some(document.clone()),
+                        ..event("replace", ns)
+                    },
+                    ref modification => ChangeEvent {
+                        document_key: Some(query.clone()),
+                        update_description: Some(UpdateDescription::new(modification)),
+                        ..event("update", ns)
+                    },
+                })
+            }
+            Operation::Delete {
+                ref namespace,
+                ref query,
+                ..
+            } => Some(ChangeEvent {
+                document_key: Some(query.clone()),
+                ..event("delete", ChangeNamespace::new(namespace))
+            }),
+            Operation::Command { ref namespace, .. } => match operation.command_kind()? {
+                CommandKind::Create { ref collection, .. } => Some(event(
+                    "create",
+                    ChangeNamespace::sibling(namespace, collection),
+                )),
+                CommandKind::Drop { ref collection } => Some(event(
+                    "drop",
+                    ChangeNamespace::sibling(namespace, collection),
+                )),
+                CommandKind::RenameCollection {
+                    ref from, ref to, ..
+                } => Some(ChangeEvent {
+                    to: Some(ChangeNamespace::new(to)),
+                    ..event("rename", ChangeNamespace::new(from))
+                }),
+                CommandKind::CollMod { ref collection, .. } => Some(event(
+                    "modify",
+                    ChangeNamespace::sibling(namespace, collection),
+                )),
+                CommandKind::CreateIndexes { ref collection, .. }
+                | CommandKind::CommitIndexBuild { ref collection, .. } => Some(event(
+                    "createIndexes",
+                    ChangeNamespace::sibling(namespace, collection),
+                )),
+                CommandKind::DropIndexes { ref collection, .. } => Some(event(
+                    "dropIndexes",
+                    ChangeNamespace::sibling(namespace, collection),
+                )),
+                CommandKind::DropDatabase { ref database } => Some(event(
+                    "dropDatabase",
+                    ChangeNamespace {
+                        db: database.clone(),
+                        coll: None,
+                    },
+                )),
+                CommandKind::StartIndexBuild { .. }
+                | CommandKind::AbortIndexBuild { .. }
+                | CommandKind::ConvertToCapped { .. }
+                | CommandKind::EmptyCapped { .. }
+                | CommandKind::Other(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl UpdateDescription {
+    /// Returns the fields changed by an update operator document or diff.
+    pub(crate) fn new(modification: &UpdateModification) -> UpdateDescription {
+        match *modification {
+            UpdateModification::Replacement(_) => UpdateDescription::default(),
+            UpdateModification::Operators(ref operators) => UpdateDescription {
+                updated_fields: operators.get_document("$set").cloned().unwrap_or_default(),
+                removed_fields: operators
+                    .get_document("$unset")
+                    .map(|unset| unset.keys().cloned().collect())
+                    .unwrap_or_default(),
+            },
+            UpdateModification::Diff {
+                ref set,
+                ref unset,
+                ref insert,
+            } => {
+                let mut updated_fields = set.clone();
+                updated_fields.extend(insert.clone());
+
+                UpdateDescription {
+                    updated_fields,
+                    removed_fields: unset.clone(),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the document key of an inserted document.
+fn document_key(document: &Document) -> Option<Document> {
+    document.get("_id").map(|id| doc! { "_id": id.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::Bson;
+
+    fn operation(o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 1 }),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : o
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn change_event_maps_inserts() {
+        let insert = Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 1 }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(
+            insert.to_change_event(),
+            Some(ChangeEvent {
+                operation_type: "insert".into(),
+                ns: ChangeNamespace {
+                    db: "foo".into(),
+                    coll: Some("bar".into()),
+                },
+                to: None,
+                document_key: Some(doc! { "_id" : 1 }),
+                full_document: Some(doc! { "_id" : 1, "foo" : "bar" }),
+                update_description: None,
+                cluster_time: bson::Timestamp {
+                    time: 1479561394,
+                    increment: 1,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn change_event_maps_update_operators() {
+        let event = operation(doc! { "$set" : { "foo" : "baz" }, "$unset" : { "qux" : true } })
+            .to_change_event()
+            .unwrap();
+
+        assert_eq!(event.operation_type, "update");
+        assert_eq!(event.document_key, Some(doc! { "_id" : 1 }));
+        assert_eq!(
+            event.update_description,
+            Some(UpdateDescription {
+                updated_fields: doc! { "foo" : "baz" },
+                removed_fields: vec!["qux".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn change_event_maps_update_diffs() {
+        let event = operation(doc! {
+            "$v" : 2,
+            "diff" : { "u" : { "foo" : "baz" }, "i" : { "new" : 1 }, "d" : { "qux" : false } }
+        })
+        .to_change_event()
+        .unwrap();
+
+        assert_eq!(
+            event.update_description,
+            Some(UpdateDescription {
+                updated_fields: doc! { "foo" : "baz", "new" : 1 },
+                removed_fields: vec!["qux".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn change_event_maps_replacements() {
+        let event = operation(doc! { "_id" : 1, "foo" : "baz" })
+            .to_change_event()
+            .unwrap();
+
+        assert_eq!(event.operation_type, "replace");
+        assert_eq!(event.full_document, Some(doc! { "_id" : 1, "foo" : "baz" }));
+        assert_eq!(event.update_description, None);
+    }
+
+    #[test]
+    fn change_event_maps_renames() {
+        let rename = Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 1 }),
+            "op" : "c",
+            "ns" : "foo.$cmd",
+            "o" : { "renameCollection" : "foo.bar", "to" : "foo.baz" }
+        })
+        .unwrap();
+        let event = rename.to_change_event().unwrap();
+
+        assert_eq!(event.operation_type, "rename");
+        assert_eq!(event.ns.coll.as_deref(), Some("bar"));
+        assert_eq!(event.to.unwrap().coll.as_deref(), Some("baz"));
+    }
+
+    #[test]
+    fn change_event_is_none_for_noops() {
+        let noop = Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 1 }),
+            "op" : "n",
+            "ns" : "",
+            "o" : { "msg" : "periodic noop" }
+        })
+        .unwrap();
+
+        assert_eq!(noop.to_change_event(), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! The checkpoint module is responsible for persisting the position of a consumer in the oplog
+//! to a file so it can resume from there after a restart.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+use mongodb::bson;
+
+/// A file recording the oplog timestamp of the last operation processed by a consumer.
+///
+/// The timestamp is saved as its time and increment on a single line. Saving writes to a
+/// temporary file alongside the checkpoint before renaming it into place so a crash mid-write
+/// never leaves a truncated checkpoint behind.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{Checkpoint, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let checkpoint = Checkpoint::new("oplog.checkpoint");
+///
+/// let mut builder = Oplog::builder();
+/// if let Some(ts) = checkpoint.load()? {
+///     builder = builder.start_at(ts);
+/// }
+/// let mut oplog = builder.build(&client).await?;
+///
+/// while let Some(res) = oplog.next().await {
+///     let oper = res?;
+///     println!("{}", oper);
+///     checkpoint.save(oper.oplog_ts())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    path: PathBuf,
+}
+
+impl Checkpoint {
+    /// Returns a checkpoint stored at the given path.
+    pub fn new<P: AsRef<Path>>(path: P) -> Checkpoint {
+        Checkpoint {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the checkpoint.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Atomically save the given timestamp, replacing any previously saved.
+    pub fn save(&self, ts: bson::Timestamp) -> Result<()> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+
+        let mut file = File::create(&temp)?;
+        writeln!(file, "{} {}", ts.time, ts.increment)?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)?;
+
+        Ok(())
+    }
+
+    /// Load the saved timestamp, returning `None` if nothing has been saved yet.
+    ///
+    /// Returns `Error::Io` if the checkpoint cannot be read or is malformed.
+    pub fn load(&self) -> Result<Option<bson::Timestamp>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut fields = contents.split_whitespace().map(str::parse::<u32>);
+
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(time)), Some(Ok(increment)), None) => {
+                Ok(Some(bson::Timestamp { time, increment }))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed checkpoint").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn checkpoint(name: &str) -> Checkpoint {
+        let path = std::env::temp_dir().join(format!("oplog-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+
+        Checkpoint::new(path)
+    }
+
+    #[test]
+    fn checkpoint_round_trips_timestamps() {
+        let checkpoint = checkpoint("round-trip");
+        let ts = bson::Timestamp {
+            time: 1479561394,
+            increment: 3,
+        };
+
+        checkpoint.save(ts).unwrap();
+        checkpoint
+            .save(bson::Timestamp { increment: 4, ..ts })
+            .unwrap();
+
+        assert_eq!(
+            checkpoint.load().unwrap(),
+            Some(bson::Timestamp {
+                time: 1479561394,
+                increment: 4,
+            })
+        );
+        fs::remove_file(checkpoint.path()).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_without_a_file_is_none() {
+        assert_eq!(checkpoint("missing").load().unwrap(), None);
+    }
+
+    #[test]
+    fn checkpoint_rejects_malformed_files() {
+        let checkpoint = checkpoint("malformed");
+        fs::write(checkpoint.path(), "garbage").unwrap();
+
+        assert!(matches!(checkpoint.load(), Err(Error::Io(_))));
+        fs::remove_file(checkpoint.path()).unwrap();
+    }
+}
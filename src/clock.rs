@@ -0,0 +1,74 @@
+//! The clock module is responsible for the source of the current time used to measure how far
+//! behind the oplog is, so lag can be controlled in tests and deterministic replays.
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+/// A source of the current time.
+///
+/// This is used by `Oplog::lag`, `OplogMetrics::on_lag` and `MergedOplog::max_skew` to measure
+/// how long ago operations were written.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A clock returning the system time.
+///
+/// This is the default for an `Oplog` without `OplogBuilder::clock`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock returning a time set by hand, e.g. to assert an exact lag in tests.
+///
+/// Clones share the same time so a clone can be given to `OplogBuilder::clock` and the original
+/// kept to move the time on.
+///
+/// # Example
+///
+/// ```
+/// use chrono::{Duration, TimeZone, Utc};
+/// use oplog::{Clock, MockClock};
+///
+/// let clock = MockClock::new(Utc.timestamp_opt(1479561394, 0).unwrap());
+/// clock.advance(Duration::seconds(5));
+///
+/// assert_eq!(clock.now(), Utc.timestamp_opt(1479561399, 0).unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    /// The current time of the clock.
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    /// Returns a clock stopped at the given time.
+    pub fn new(now: DateTime<Utc>) -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Set the current time of the clock.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Move the current time of the clock on by the given duration.
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,533 @@
+//! The coalesce module is responsible for merging consecutive updates to the same document so
+//! batch sinks write each changed document once per batch rather than once per operation.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{FieldChange, Operation, UpdateModification};
+use bson::{Bson, Document};
+use mongodb::bson;
+
+/// The namespace and serialized `_id` identifying a document.
+pub(crate) type DocumentKey = (String, Vec<u8>);
+
+/// Merges updates to the same document within a window of operations.
+///
+/// Operations are pushed in oplog order and buffered until the window is full, at which point
+/// they are returned together. Within the window, an update whose changes can be combined with
+/// an earlier insert or update of the same document (by namespace and `_id`) is merged into it:
+///
+/// * An update following an insert is applied to the inserted document, leaving a single insert.
+/// * Updates setting or removing fields (with `$set`/`$unset` or a `$v: 2` diff) are combined
+///   into a single update, with the later value of any field changed by both winning.
+/// * A replacement replaces the earlier document or update entirely.
+///
+/// Merged operations keep the position of the earlier operation (including its timestamps) so
+/// operations on different documents are returned in the order they were first pushed. Any other
+/// operation on a document (e.g. a delete or an update with other operators such as `$inc`) is
+/// kept as is and ends merging for that document, and commands end merging for every document.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{Coalescer, Oplog};
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut oplog = Oplog::new(&client).await?;
+/// let mut coalescer = Coalescer::new()
+///     .max_operations(1000)
+///     .max_age(Duration::from_secs(1));
+///
+/// while let Some(res) = oplog.next().await {
+///     for oper in coalescer.push(res?) {
+///         println!("{}", oper);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Coalescer {
+    /// The maximum number of operations buffered before they are returned.
+    max_operations: usize,
+    /// The maximum time operations are buffered before they are returned.
+    max_age: Option<Duration>,
+    /// The buffered operations in the order they were first pushed.
+    buffered: Vec<Operation>,
+    /// The index of the buffered operation each later update of a document may merge into.
+    mergeable: HashMap<DocumentKey, usize>,
+    /// When the first buffered operation was pushed.
+    started: Option<Instant>,
+}
+
+impl Default for Coalescer {
+    fn default() -> Coalescer {
+        Coalescer {
+            max_operations: 100,
+            max_age: None,
+            buffered: Vec::new(),
+            mergeable: HashMap::new(),
+            started: None,
+        }
+    }
+}
+
+impl Coalescer {
+    /// Creates an empty coalescer returning operations in windows of 100.
+    pub fn new() -> Coalescer {
+        Coalescer::default()
+    }
+
+    /// Set the maximum number of operations buffered before they are returned.
+    ///
+    /// Operations merged into earlier ones do not count towards this. Defaults to 100.
+    pub fn max_operations(mut self, max_operations: usize) -> Self {
+        self.max_operations = max_operations.max(1);
+        self
+    }
+
+    /// Set the maximum time operations are buffered before they are returned.
+    ///
+    /// This is checked as operations are pushed so, on a quiet oplog, callers should also call
+    /// `flush` periodically (e.g. on each heartbeat with `OplogBuilder::heartbeats`). By default,
+    /// operations are only returned once `max_operations` are buffered.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Push the next operation from the oplog, returning the buffered operations if the window is
+    /// full.
+    pub fn push(&mut self, operation: Operation) -> Vec<Operation> {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        match document_key(&operation) {
+            Some(key) => {
+                let merged = self
+                    .mergeable
+                    .get(&key)
+                    .and_then(|&index| merge(&self.buffered[index], &operation));
+
+                match merged {
+                    Some(merged) => self.buffered[self.mergeable[&key]] = merged,
+                    None => {
+                        if is_mergeable(&operation) {
+                            self.mergeable.insert(key, self.buffered.len());
+                        } else {
+                            self.mergeable.remove(&key);
+                        }
+                        self.buffered.push(operation);
+                    }
+                }
+            }
+            None => {
+                if !operation.is_noop() {
+                    self.mergeable.clear();
+                }
+                self.buffered.push(operation);
+            }
+        }
+
+        let expired = match (self.max_age, self.started) {
+            (Some(max_age), Some(started)) => started.elapsed() >= max_age,
+            _ => false,
+        };
+
+        if expired || self.buffered.len() >= self.max_operations {
+            self.flush()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Return all buffered operations, e.g. when the oplog ends or is checkpointed.
+    pub fn flush(&mut self) -> Vec<Operation> {
+        self.mergeable.clear();
+        self.started = None;
+
+        std::mem::take(&mut self.buffered)
+    }
+
+    /// Returns the number of operations currently buffered.
+    pub fn pending(&self) -> usize {
+        self.buffered.len()
+    }
+}
+
+/// Returns the namespace and serialized `_id` of the document affected by an insert, update or
+/// delete.
+pub(crate) fn document_key(operation: &Operation) -> Option<DocumentKey> {
+    let namespace = operation.namespace()?;
+    let id = operation.object_id()?;
+    let mut document = Document::new();
+    document.insert("_id", id.clone());
+
+    Some((namespace.into(), bson::to_vec(&document).ok()?))
+}
+
+/// Returns whether later updates may be merged into an operation.
+fn is_mergeable(operation: &Operation) -> bool {
+    match *operation {
+        Operation::Insert { .. } => true,
+        Operation::Update {
+            ref modification, ..
+        } => {
+            matches!(*modification, UpdateModification::Replacement(_))
+                || changes(modification).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Returns the `$set` and `$unset` changes of an update, if it has no other changes.
+fn changes(modification: &UpdateModification) -> Option<Vec<FieldChange>> {
+    let changes = modification.field_changes()?;
+
+    if changes
+        .iter()
+        .all(|change| matches!(*change, FieldChange::Set { .. } | FieldChange::Unset { .. }))
+    {
+        Some(changes)
+    } else {
+        None
+    }
+}
+
+/// Returns the operation merging a later update into an earlier operation on the same document,
+/// if they can be merged.
+fn merge(earlier: &Operation, later: &Operation) -> Option<Operation> {
+    let (later_update, later_modification) = match *later {
+        Operation::Update {
+            ref update,
+            ref modification,
+            ..
+        } => (update, modification),
+        _ => return None,
+    };
+
+    match *earlier {
+        Operation::Insert { ref document, .. } => {
+            let document = match *later_modification {
+                UpdateModification::Replacement(ref replacement) => {
+                    with_id(replacement, document.get("_id"))
+                }
+                ref modification => applied(document, &changes(modification)?)?,
+            };
+
+            let mut merged = earlier.clone();
+            if let Operation::Insert {
+                document: ref mut merged_document,
+                ..
+            } = merged
+            {
+                *merged_document = document;
+            }
+
+            Some(merged)
+        }
+        Operation::Update {
+            ref modification, ..
+        } => {
+            let update = match (modification, later_modification) {
+                (_, UpdateModification::Replacement(_)) => later_update.clone(),
+                (UpdateModification::Replacement(document), later_modification) => {
+                    applied(document, &changes(later_modification)?)?
+                }
+                (modification, later_modification) => {
+                    combined(changes(modification)?, changes(later_modification)?)?
+                }
+            };
+            let modification = UpdateModification::new(&update).ok()?;
+
+            let mut merged = earlier.clone();
+            if let Operation::Update {
+                update: ref mut merged_update,
+                modification: ref mut merged_modification,
+                ..
+            } = merged
+            {
+                *merged_update = update;
+                *merged_modification = modification;
+            }
+
+            Some(merged)
+        }
+        _ => None,
+    }
+}
+
+/// Returns a replacement document with the given `_id` if it has none of its own.
+fn with_id(document: &Document, id: Option<&Bson>) -> Document {
+    match id {
+        Some(id) if !document.contains_key("_id") => {
+            let mut replaced = Document::new();
+            replaced.insert("_id", id.clone());
+            replaced.extend(document.clone());
+
+            replaced
+        }
+        _ => document.clone(),
+    }
+}
+
+/// Returns the document with the given changes applied, if every field path can be applied.
+fn applied(document: &Document, changes: &[FieldChange]) -> Option<Document> {
+    let mut document = document.clone();
+
+    for change in changes {
+        match *change {
+            FieldChange::Set {
+                ref path,
+                ref value,
+            } => set_path(&mut document, path, value.clone())?,
+            FieldChange::Unset { ref path } => unset_path(&mut document, path)?,
+            _ => return None,
+        }
+    }
+
+    Some(document)
+}
+
+/// Returns the `$set` and `$unset` operators of two sets of changes with later changes winning,
+/// if none of their field paths overlap without being equal (e.g. `a` and `a.b`).
+fn combined(earlier: Vec<FieldChange>, later: Vec<FieldChange>) -> Option<Document> {
+    let mut changes: Vec<FieldChange> = Vec::new();
+
+    for change in earlier.into_iter().chain(later) {
+        let path = path_of(&change).to_string();
+
+        if changes
+            .iter()
+            .any(|existing| overlaps(path_of(existing), &path))
+        {
+            return None;
+        }
+        changes.retain(|existing| path_of(existing) != path);
+        changes.push(change);
+    }
+
+    let mut set = Document::new();
+    let mut unset = Document::new();
+    for change in changes {
+        match change {
+            FieldChange::Set { path, value } => {
+                set.insert(path, value);
+            }
+            FieldChange::Unset { path } => {
+                unset.insert(path, "");
+            }
+            _ => return None,
+        }
+    }
+
+    let mut operators = Document::new();
+    if !set.is_empty() {
+        operators.insert("$set", set);
+    }
+    if !unset.is_empty() {
+        operators.insert("$unset", unset);
+    }
+
+    Some(operators)
+}
+
+/// Returns the field path of a `$set` or `$unset` change.
+fn path_of(change: &FieldChange) -> &str {
+    match *change {
+        FieldChange::Set { ref path, .. }
+        | FieldChange::Unset { ref path }
+        | FieldChange::Other { ref path, .. } => path,
+        FieldChange::Rename { ref from, .. } => from,
+    }
+}
+
+/// Returns whether two different field paths refer to overlapping fields.
+fn overlaps(a: &str, b: &str) -> bool {
+    a != b && (is_prefix(a, b) || is_prefix(b, a))
+}
+
+/// Returns whether `prefix` is a parent field path of `path`.
+fn is_prefix(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Set the field at a dotted path, creating embedded documents as needed. Returns `None` if the
+/// path passes through a value which is not a document (e.g. an array).
+fn set_path(document: &mut Document, path: &str, value: Bson) -> Option<()> {
+    match path.split_once('.') {
+        None => {
+            document.insert(path, value);
+        }
+        Some((field, rest)) => {
+            if !document.contains_key(field) {
+                document.insert(field, Document::new());
+            }
+            match document.get_mut(field)? {
+                Bson::Document(ref mut embedded) => set_path(embedded, rest, value)?,
+                _ => return None,
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// Remove the field at a dotted path. Returns `None` if the path passes through a value which is
+/// not a document (e.g. an array).
+fn unset_path(document: &mut Document, path: &str) -> Option<()> {
+    match path.split_once('.') {
+        None => {
+            document.remove(path);
+        }
+        Some((field, rest)) => match document.get_mut(field) {
+            None => {}
+            Some(Bson::Document(ref mut embedded)) => unset_path(embedded, rest)?,
+            Some(_) => return None,
+        },
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn operation(increment: u32, op: &str, id: i32, o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment }),
+            "op" : op,
+            "ns" : "foo.bar",
+            "o2" : { "_id" : id },
+            "o" : o
+        })
+        .unwrap()
+    }
+
+    fn update(increment: u32, id: i32, o: Document) -> Operation {
+        operation(increment, "u", id, o)
+    }
+
+    #[test]
+    fn coalescer_merges_updates_to_the_same_document() {
+        let mut coalescer = Coalescer::new();
+
+        coalescer.push(update(1, 1, doc! { "$set" : { "a" : 1 } }));
+        coalescer.push(update(2, 1, doc! { "$set" : { "b" : 2 } }));
+        let operations = coalescer.flush();
+
+        assert_eq!(operations.len(), 1);
+        match operations[0] {
+            Operation::Update {
+                ref update,
+                ref modification,
+                optime,
+                ..
+            } => {
+                assert_eq!(update, &doc! { "$set" : { "a" : 1, "b" : 2 } });
+                assert_eq!(
+                    modification,
+                    &UpdateModification::Operators(doc! { "$set" : { "a" : 1, "b" : 2 } })
+                );
+                assert_eq!(optime.increment, 1);
+            }
+            _ => panic!("Expected update."),
+        }
+    }
+
+    #[test]
+    fn coalescer_keeps_the_last_write_of_conflicting_fields() {
+        let mut coalescer = Coalescer::new();
+
+        coalescer.push(update(
+            1,
+            1,
+            doc! { "$set" : { "a" : 1 }, "$unset" : { "c" : "" } },
+        ));
+        coalescer.push(update(
+            2,
+            1,
+            doc! { "$v" : 2, "diff" : { "u" : { "a" : 2, "c" : 3 } } },
+        ));
+
+        assert_eq!(
+            coalescer.flush()[0],
+            update(1, 1, doc! { "$set" : { "a" : 2, "c" : 3 } })
+        );
+    }
+
+    #[test]
+    fn coalescer_applies_updates_to_inserts() {
+        let mut coalescer = Coalescer::new();
+
+        coalescer.push(operation(1, "i", 1, doc! { "_id" : 1, "a" : { "b" : 1 } }));
+        coalescer.push(update(2, 1, doc! { "$set" : { "a.b" : 2, "c" : 3 } }));
+
+        assert_eq!(
+            coalescer.flush(),
+            vec![operation(
+                1,
+                "i",
+                1,
+                doc! { "_id" : 1, "a" : { "b" : 2 }, "c" : 3 }
+            )]
+        );
+    }
+
+    #[test]
+    fn coalescer_preserves_order_across_documents() {
+        let mut coalescer = Coalescer::new();
+
+        coalescer.push(update(1, 1, doc! { "$set" : { "a" : 1 } }));
+        coalescer.push(update(2, 2, doc! { "$set" : { "a" : 1 } }));
+        coalescer.push(update(3, 1, doc! { "$set" : { "a" : 2 } }));
+        coalescer.push(operation(4, "d", 2, doc! { "_id" : 2 }));
+        coalescer.push(update(5, 2, doc! { "$set" : { "a" : 3 } }));
+
+        let operations = coalescer.flush();
+        let positions: Vec<(u32, &str)> = operations
+            .iter()
+            .map(|operation| (operation.oplog_ts().increment, operation.op_char()))
+            .collect();
+
+        assert_eq!(positions, vec![(1, "u"), (2, "u"), (4, "d"), (5, "u")]);
+    }
+
+    #[test]
+    fn coalescer_does_not_merge_other_operators() {
+        let mut coalescer = Coalescer::new();
+
+        coalescer.push(update(1, 1, doc! { "$set" : { "a" : 1 } }));
+        coalescer.push(update(2, 1, doc! { "$inc" : { "a" : 1 } }));
+        coalescer.push(update(3, 1, doc! { "$set" : { "a" : 3 } }));
+
+        assert_eq!(coalescer.pending(), 3);
+    }
+
+    #[test]
+    fn coalescer_returns_full_windows() {
+        let mut coalescer = Coalescer::new().max_operations(2);
+
+        assert!(coalescer
+            .push(update(1, 1, doc! { "$set" : { "a" : 1 } }))
+            .is_empty());
+        assert!(coalescer
+            .push(update(2, 1, doc! { "$set" : { "a" : 2 } }))
+            .is_empty());
+        assert_eq!(
+            coalescer
+                .push(update(3, 2, doc! { "$set" : { "a" : 1 } }))
+                .len(),
+            2
+        );
+        assert_eq!(coalescer.pending(), 0);
+    }
+}
@@ -0,0 +1,502 @@
+//! The command module is responsible for classifying the documents of command operations (e.g.
+//! index builds or collection renames) so consumers need not inspect them by hand.
+
+use bson::{Bson, Document};
+use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The kind of an `Operation::Command` as determined by its command document.
+///
+/// This is returned by `Operation::command_kind`.
+///
+/// # Example
+///
+/// ```
+/// use oplog::bson::{self, doc, Bson};
+/// use oplog::{CommandKind, Operation};
+///
+/// let document = doc! {
+///     "ts": Bson::Timestamp(bson::Timestamp {
+///         time: 1479561394,
+///         increment: 0,
+///     }),
+///     "op": "c",
+///     "ns": "foo.$cmd",
+///     "o": {
+///         "createIndexes": "bar",
+///         "v": 2,
+///         "key": { "baz": 1 },
+///         "name": "baz_1"
+///     }
+/// };
+/// let operation = Operation::new(&document)?;
+///
+/// match operation.command_kind() {
+///     Some(CommandKind::CreateIndexes { collection, .. }) => assert_eq!(collection, "bar"),
+///     _ => panic!("Expected createIndexes."),
+/// }
+/// # Ok::<(), oplog::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CommandKind {
+    /// The creation of one or more indexes (`createIndexes` or `createIndex`).
+    CreateIndexes {
+        /// The collection being indexed.
+        collection: String,
+        /// The specifications of the indexes being created.
+        indexes: Vec<Document>,
+    },
+    /// The start of a two-phase index build (`startIndexBuild`, MongoDB 4.4+).
+    ///
+    /// The indexes are not usable until the build with the same UUID is committed.
+    StartIndexBuild {
+        /// The collection being indexed.
+        collection: String,
+        /// The UUID identifying the index build across its phases.
+        build_uuid: bson::Uuid,
+        /// The specifications of the indexes being built.
+        indexes: Vec<Document>,
+    },
+    /// The completion of a two-phase index build (`commitIndexBuild`), making its indexes usable.
+    CommitIndexBuild {
+        /// The collection being indexed.
+        collection: String,
+        /// The UUID identifying the index build across its phases.
+        build_uuid: bson::Uuid,
+        /// The specifications of the indexes built.
+        indexes: Vec<Document>,
+    },
+    /// The cancellation of a two-phase index build (`abortIndexBuild`), discarding its indexes.
+    AbortIndexBuild {
+        /// The collection being indexed.
+        collection: String,
+        /// The UUID identifying the index build across its phases.
+        build_uuid: bson::Uuid,
+        /// The specifications of the indexes abandoned.
+        indexes: Vec<Document>,
+        /// The error which caused the build to be aborted, if recorded.
+        cause: Option<Document>,
+    },
+    /// The deletion of one or more indexes (`dropIndexes` or `deleteIndexes`).
+    DropIndexes {
+        /// The collection whose indexes are dropped.
+        collection: String,
+        /// The name, key pattern or list of names of the dropped indexes (or `"*"` for all).
+        index: Bson,
+    },
+    /// The creation of a collection or view (`create`).
+    Create {
+        /// The collection being created.
+        collection: String,
+        /// The options of the collection such as `capped` or `validator`.
+        options: Document,
+    },
+    /// The deletion of a collection (`drop`).
+    Drop {
+        /// The collection being dropped.
+        collection: String,
+    },
+    /// The renaming of a collection (`renameCollection`).
+    RenameCollection {
+        /// The original full namespace of the collection.
+        from: String,
+        /// The new full namespace of the collection.
+        to: String,
+        /// Whether any existing collection at the new namespace is dropped.
+        drop_target: bool,
+    },
+    /// The modification of a collection's options (`collMod`).
+    CollMod {
+        /// The collection being modified.
+        collection: String,
+        /// The options being changed such as `validator` or `index`.
+        changes: Document,
+    },
+    /// The conversion of an existing collection into a capped collection (`convertToCapped`).
+    ConvertToCapped {
+        /// The collection being converted.
+        collection: String,
+        /// The maximum size of the capped collection in bytes.
+        size: i64,
+    },
+    /// The removal of every document from a capped collection (`emptycapped`).
+    EmptyCapped {
+        /// The collection being emptied.
+        collection: String,
+    },
+    /// The deletion of a whole database and all of its collections (`dropDatabase`).
+    DropDatabase {
+        /// The database being dropped, as given by the command's namespace.
+        database: String,
+    },
+    /// Any other command.
+    Other(Document),
+}
+
+impl CommandKind {
+    /// Classify a command document run in the given namespace (e.g. `foo.$cmd`).
+    ///
+    /// Commands which are recognized but malformed (e.g. missing their collection) are returned
+    /// as `CommandKind::Other`.
+    pub(crate) fn new(namespace: &str, command: &Document) -> CommandKind {
+        CommandKind::classify(namespace, command)
+            .unwrap_or_else(|| CommandKind::Other(command.clone()))
+    }
+
+    /// Returns the kind of a recognized and well-formed command.
+    fn classify(namespace: &str, command: &Document) -> Option<CommandKind> {
+        let (name, value) = command.iter().next()?;
+
+        match name.as_str() {
+            "createIndexes" | "createIndex" => Some(CommandKind::CreateIndexes {
+                collection: value.as_str()?.into(),
+                indexes: index_specs(name, command)?,
+            }),
+            "startIndexBuild" => Some(CommandKind::StartIndexBuild {
+                collection: value.as_str()?.into(),
+                build_uuid: build_uuid(command)?,
+                indexes: index_specs(name, command)?,
+            }),
+            "commitIndexBuild" => Some(CommandKind::CommitIndexBuild {
+                collection: value.as_str()?.into(),
+                build_uuid: build_uuid(command)?,
+                indexes: index_specs(name, command)?,
+            }),
+            "abortIndexBuild" => Some(CommandKind::AbortIndexBuild {
+                collection: value.as_str()?.into(),
+                build_uuid: build_uuid(command)?,
+                indexes: index_specs(name, command)?,
+                cause: match command.get("cause") {
+                    Some(cause) => Some(cause.as_document()?.clone()),
+                    None => None,
+                },
+            }),
+            "dropIndexes" | "deleteIndexes" => Some(CommandKind::DropIndexes {
+                collection: value.as_str()?.into(),
+                index: command.get("index")?.clone(),
+            }),
+            "create" => Some(CommandKind::Create {
+                collection: value.as_str()?.into(),
+                options: without(name, command),
+            }),
+            "drop" => Some(CommandKind::Drop {
+                collection: value.as_str()?.into(),
+            }),
+            "renameCollection" => Some(CommandKind::RenameCollection {
+                from: value.as_str()?.into(),
+                to: command.get_str("to").ok()?.into(),
+                drop_target: drop_target(command)?,
+            }),
+            "collMod" => Some(CommandKind::CollMod {
+                collection: value.as_str()?.into(),
+                changes: without(name, command),
+            }),
+            "convertToCapped" => Some(CommandKind::ConvertToCapped {
+                collection: value.as_str()?.into(),
+                size: integer(command.get("size")?)?,
+            }),
+            "emptycapped" => Some(CommandKind::EmptyCapped {
+                collection: value.as_str()?.into(),
+            }),
+            "dropDatabase" => Some(CommandKind::DropDatabase {
+                database: namespace.split_once('.')?.0.into(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the index specifications of an index creation command.
+///
+/// Oplog entries record a single specification inline alongside the collection (e.g.
+/// `{createIndexes: "bar", v: 2, key: {baz: 1}, name: "baz_1"}`) while the command as issued by
+/// clients lists them in `indexes`.
+fn index_specs(name: &str, command: &Document) -> Option<Vec<Document>> {
+    match command.get("indexes") {
+        Some(Bson::Array(indexes)) => indexes
+            .iter()
+            .map(|index| index.as_document().cloned())
+            .collect(),
+        Some(_) => None,
+        None => Some(vec![without(name, command)]),
+    }
+}
+
+/// Returns the UUID identifying the index build of a two-phase index build command.
+fn build_uuid(command: &Document) -> Option<bson::Uuid> {
+    match command.get("indexBuildUUID") {
+        Some(Bson::Binary(binary)) => binary.to_uuid().ok(),
+        _ => None,
+    }
+}
+
+/// Returns whether a `renameCollection` command drops its target, which is recorded in the oplog
+/// as either a boolean or the UUID of the dropped collection.
+fn drop_target(command: &Document) -> Option<bool> {
+    match command.get("dropTarget") {
+        None => Some(false),
+        Some(&Bson::Boolean(drop_target)) => Some(drop_target),
+        Some(&Bson::Binary(_)) => Some(true),
+        Some(_) => None,
+    }
+}
+
+/// Returns a whole number recorded as any numeric BSON type.
+fn integer(value: &Bson) -> Option<i64> {
+    match *value {
+        Bson::Int32(value) => Some(value.into()),
+        Bson::Int64(value) => Some(value),
+        Bson::Double(value) if value.fract() == 0.0 => Some(value as i64),
+        _ => None,
+    }
+}
+
+/// Returns a copy of a command document without its leading command name.
+fn without(name: &str, command: &Document) -> Document {
+    let mut rest = command.clone();
+    rest.remove(name);
+
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn command_kind_classifies_oplog_create_indexes() {
+        let command =
+            doc! { "createIndexes" : "bar", "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::CreateIndexes {
+                collection: "bar".into(),
+                indexes: vec![doc! { "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" }],
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_create_index_lists() {
+        let command = doc! {
+            "createIndex" : "bar",
+            "indexes" : [{ "key" : { "baz" : 1 }, "name" : "baz_1" }]
+        };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::CreateIndexes {
+                collection: "bar".into(),
+                indexes: vec![doc! { "key" : { "baz" : 1 }, "name" : "baz_1" }],
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_start_index_build() {
+        let uuid = bson::Uuid::new();
+        let command = doc! {
+            "startIndexBuild" : "bar",
+            "indexBuildUUID" : uuid,
+            "indexes" : [{ "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" }]
+        };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::StartIndexBuild {
+                collection: "bar".into(),
+                build_uuid: uuid,
+                indexes: vec![doc! { "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" }],
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_commit_index_build() {
+        let uuid = bson::Uuid::new();
+        let command = doc! {
+            "commitIndexBuild" : "bar",
+            "indexBuildUUID" : uuid,
+            "indexes" : [{ "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" }]
+        };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::CommitIndexBuild {
+                collection: "bar".into(),
+                build_uuid: uuid,
+                indexes: vec![doc! { "v" : 2, "key" : { "baz" : 1 }, "name" : "baz_1" }],
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_requires_index_build_uuids() {
+        let command = doc! { "commitIndexBuild" : "bar", "indexes" : [] };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::Other(command)
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_drop_indexes() {
+        let command = doc! { "dropIndexes" : "bar", "index" : "baz_1" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::DropIndexes {
+                collection: "bar".into(),
+                index: "baz_1".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_delete_indexes() {
+        let command = doc! { "deleteIndexes" : "bar", "index" : { "baz" : 1 } };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::DropIndexes {
+                collection: "bar".into(),
+                index: Bson::Document(doc! { "baz" : 1 }),
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_create() {
+        let command = doc! { "create" : "bar", "capped" : true, "size" : 1024 };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::Create {
+                collection: "bar".into(),
+                options: doc! { "capped" : true, "size" : 1024 },
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_drop() {
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &doc! { "drop" : "bar" }),
+            CommandKind::Drop {
+                collection: "bar".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_rename_collection() {
+        let command = doc! {
+            "renameCollection" : "foo.bar",
+            "to" : "foo.baz",
+            "stayTemp" : false,
+            "dropTarget" : bson::Uuid::new()
+        };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::RenameCollection {
+                from: "foo.bar".into(),
+                to: "foo.baz".into(),
+                drop_target: true,
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_rename_collection_without_drop_target() {
+        let command = doc! { "renameCollection" : "foo.bar", "to" : "qux.bar" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::RenameCollection {
+                from: "foo.bar".into(),
+                to: "qux.bar".into(),
+                drop_target: false,
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_coll_mod() {
+        let command = doc! { "collMod" : "bar", "validationLevel" : "strict" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::CollMod {
+                collection: "bar".into(),
+                changes: doc! { "validationLevel" : "strict" },
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_returns_other_commands() {
+        let command = doc! { "dropIndexes" : 1 };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::Other(command)
+        );
+
+        let command = doc! { "renameCollection" : "foo.bar" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::Other(command)
+        );
+
+        let command = doc! { "dbCheck" : "bar" };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::Other(command)
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_convert_to_capped() {
+        let command = doc! { "convertToCapped" : "bar", "size" : 4096.0 };
+
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &command),
+            CommandKind::ConvertToCapped {
+                collection: "bar".into(),
+                size: 4096,
+            }
+        );
+        assert!(matches!(
+            CommandKind::new("foo.$cmd", &doc! { "convertToCapped" : "bar" }),
+            CommandKind::Other(_)
+        ));
+    }
+
+    #[test]
+    fn command_kind_classifies_empty_capped() {
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &doc! { "emptycapped" : "bar" }),
+            CommandKind::EmptyCapped {
+                collection: "bar".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn command_kind_classifies_drop_database() {
+        assert_eq!(
+            CommandKind::new("foo.$cmd", &doc! { "dropDatabase" : 1 }),
+            CommandKind::DropDatabase {
+                database: "foo".into(),
+            }
+        );
+    }
+}
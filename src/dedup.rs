@@ -0,0 +1,258 @@
+//! The dedup module is responsible for discarding all but the last operation on each document
+//! within a window, e.g. when replaying a range of the oplog after a crash.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::coalesce::{document_key, DocumentKey};
+use crate::Operation;
+
+/// Keeps only the last operation on each document within a window of operations.
+///
+/// Operations are pushed in oplog order and buffered until the window is full, at which point
+/// they are returned together. Within the window, an insert, update or delete replaces any
+/// earlier operation on the same document (by namespace and `_id`), e.g. an insert followed by a
+/// delete leaves only the delete, and the operations left are returned in the order of their own
+/// positions in the oplog.
+///
+/// Unlike `Coalescer`, earlier operations are discarded entirely rather than merged so, for
+/// example, the fields set by an earlier update are lost if a later update sets different
+/// fields. This is only suitable for sinks which do not depend on intermediate states, e.g. ones
+/// which re-read the current document by `_id` for each operation. Commands end deduplication
+/// for every document buffered before them.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{Dedup, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut oplog = Oplog::new(&client).await?;
+/// let mut dedup = Dedup::new().max_operations(1000);
+///
+/// while let Some(res) = oplog.next().await {
+///     for oper in dedup.push(res?) {
+///         println!("{}", oper);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Dedup {
+    /// The maximum number of operations buffered before they are returned.
+    max_operations: usize,
+    /// The maximum time operations are buffered before they are returned.
+    max_age: Option<Duration>,
+    /// The buffered operations with those replaced by later operations removed.
+    buffered: Vec<Option<Operation>>,
+    /// The number of operations buffered and not replaced.
+    len: usize,
+    /// The index of the last buffered operation on each document.
+    latest: HashMap<DocumentKey, usize>,
+    /// When the first buffered operation was pushed.
+    started: Option<Instant>,
+}
+
+impl Default for Dedup {
+    fn default() -> Dedup {
+        Dedup {
+            max_operations: 100,
+            max_age: None,
+            buffered: Vec::new(),
+            len: 0,
+            latest: HashMap::new(),
+            started: None,
+        }
+    }
+}
+
+impl Dedup {
+    /// Creates an empty deduplicator returning operations in windows of 100.
+    pub fn new() -> Dedup {
+        Dedup::default()
+    }
+
+    /// Set the maximum number of operations buffered before they are returned.
+    ///
+    /// Operations replaced by later ones do not count towards this. Defaults to 100.
+    pub fn max_operations(mut self, max_operations: usize) -> Self {
+        self.max_operations = max_operations.max(1);
+        self
+    }
+
+    /// Set the maximum time operations are buffered before they are returned.
+    ///
+    /// This is checked as operations are pushed so, on a quiet oplog, callers should also call
+    /// `flush` periodically. By default, operations are only returned once `max_operations` are
+    /// buffered.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Push the next operation from the oplog, returning the buffered operations if the window is
+    /// full.
+    pub fn push(&mut self, operation: Operation) -> Vec<Operation> {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        match document_key(&operation) {
+            Some(key) => {
+                if let Some(index) = self.latest.insert(key, self.buffered.len()) {
+                    self.buffered[index] = None;
+                    self.len -= 1;
+                }
+            }
+            None if !operation.is_noop() => self.latest.clear(),
+            None => {}
+        }
+        self.buffered.push(Some(operation));
+        self.len += 1;
+
+        let expired = match (self.max_age, self.started) {
+            (Some(max_age), Some(started)) => started.elapsed() >= max_age,
+            _ => false,
+        };
+
+        if expired || self.len >= self.max_operations {
+            self.flush()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Return all buffered operations, e.g. when the oplog ends or is checkpointed.
+    pub fn flush(&mut self) -> Vec<Operation> {
+        self.latest.clear();
+        self.started = None;
+        self.len = 0;
+
+        std::mem::take(&mut self.buffered)
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Returns the number of operations currently buffered.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson};
+    use mongodb::bson;
+
+    fn ts(increment: u32) -> Bson {
+        Bson::Timestamp(bson::Timestamp {
+            time: 1479561394,
+            increment,
+        })
+    }
+
+    #[test]
+    fn dedup_collapses_insert_then_delete_into_the_delete() {
+        let mut dedup = Dedup::new();
+        let delete = Operation::new(&doc! {
+            "ts" : ts(2),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        dedup.push(
+            Operation::new(&doc! {
+                "ts" : ts(1),
+                "op" : "i",
+                "ns" : "foo.bar",
+                "o" : { "_id" : 1 }
+            })
+            .unwrap(),
+        );
+        dedup.push(delete.clone());
+
+        assert_eq!(dedup.pending(), 1);
+        assert_eq!(dedup.flush(), vec![delete]);
+    }
+
+    #[test]
+    fn dedup_keeps_the_last_update_of_each_document() {
+        let mut dedup = Dedup::new();
+        let update = |increment: u32, id: i32, a: i32| {
+            Operation::new(&doc! {
+                "ts" : ts(increment),
+                "op" : "u",
+                "ns" : "foo.bar",
+                "o2" : { "_id" : id },
+                "o" : { "$set" : { "a" : a } }
+            })
+            .unwrap()
+        };
+
+        dedup.push(update(1, 1, 1));
+        dedup.push(update(2, 2, 1));
+        dedup.push(update(3, 1, 2));
+
+        let positions: Vec<u32> = dedup
+            .flush()
+            .iter()
+            .map(|operation| operation.oplog_ts().increment)
+            .collect();
+
+        assert_eq!(positions, vec![2, 3]);
+    }
+
+    #[test]
+    fn dedup_does_not_discard_operations_before_commands() {
+        let mut dedup = Dedup::new();
+        let insert = |increment: u32| {
+            Operation::new(&doc! {
+                "ts" : ts(increment),
+                "op" : "i",
+                "ns" : "foo.bar",
+                "o" : { "_id" : 1 }
+            })
+            .unwrap()
+        };
+
+        dedup.push(insert(1));
+        dedup.push(
+            Operation::new(&doc! {
+                "ts" : ts(2),
+                "op" : "c",
+                "ns" : "foo.$cmd",
+                "o" : { "drop" : "bar" }
+            })
+            .unwrap(),
+        );
+        dedup.push(insert(3));
+
+        assert_eq!(dedup.pending(), 3);
+    }
+
+    #[test]
+    fn dedup_returns_full_windows() {
+        let mut dedup = Dedup::new().max_operations(2);
+        let write = |increment: u32, op: &str, id: i32| {
+            Operation::new(&doc! {
+                "ts" : ts(increment),
+                "op" : op,
+                "ns" : "foo.bar",
+                "o" : { "_id" : id }
+            })
+            .unwrap()
+        };
+
+        assert!(dedup.push(write(1, "i", 1)).is_empty());
+        assert!(dedup.push(write(2, "d", 1)).is_empty());
+        assert_eq!(dedup.push(write(3, "i", 2)).len(), 2);
+    }
+}
@@ -1,3 +1,4 @@
+use bson::document::ValueAccessError;
 use mongodb::bson;
 use std::fmt;
 
@@ -5,6 +6,9 @@ use std::fmt;
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Error enumerates the list of possible error conditions when tailing an oplog.
+///
+/// Variants wrapping a lower-level error (e.g. from the MongoDB driver or BSON library) return it
+/// from `std::error::Error::source` so the full chain of causes can be reported, e.g. by `anyhow`.
 #[derive(Debug)]
 pub enum Error {
     /// A database connectivity error raised by the MongoDB driver.
@@ -15,8 +19,111 @@ pub enum Error {
     /// An error when converting a BSON document to an `Operation` and it has an unsupported
     /// operation type.
     UnknownOperation(String),
-    /// An error when converting an applyOps command with invalid documents.
+    /// An error when converting an operation with invalid documents (e.g. an applyOps command or
+    /// an update diff).
     InvalidOperation,
+    /// An error when converting an applyOps command nested deeper than the configured limit.
+    MaxDepthExceeded,
+    /// An error when a parsed operation is semantically dubious (see `Operation::validate`),
+    /// describing the problem.
+    Validation(String),
+    /// An error when raw bytes could not be deserialized into a BSON document.
+    Deserialize(bson::de::Error),
+    /// An error when the oplog could not be reconnected after exhausting its retry policy,
+    /// wrapping the last error encountered.
+    ReconnectExhausted(Box<Error>),
+    /// An error when reading the position of an oplog which has no entries.
+    EmptyOplog,
+    /// An I/O error, e.g. when reading or writing a `Checkpoint`.
+    Io(std::io::Error),
+}
+
+/// The broad class of an `Error`, used to decide whether to retry or abort.
+///
+/// # Example
+///
+/// ```
+/// use oplog::{Error, ErrorKind};
+///
+/// let err = Error::UnknownOperation("x".into());
+///
+/// assert_eq!(err.kind(), ErrorKind::Parse);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A temporary connection or cursor failure which may succeed if retried.
+    Transient,
+    /// A failure to convert an oplog document into an `Operation`.
+    Parse,
+    /// Any other failure which is unlikely to succeed if retried.
+    Fatal,
+}
+
+/// The server error code of a cursor which no longer exists on the server.
+const CURSOR_NOT_FOUND: i32 = 43;
+
+/// Server error codes indicating a temporary failure such as a replica set state change.
+const TRANSIENT_CODES: &[i32] = &[
+    6,     // HostUnreachable
+    7,     // HostNotFound
+    43,    // CursorNotFound
+    89,    // NetworkTimeout
+    91,    // ShutdownInProgress
+    189,   // PrimarySteppedDown
+    262,   // ExceededTimeLimit
+    9001,  // SocketException
+    10107, // NotWritablePrimary
+    11600, // InterruptedAtShutdown
+    11602, // InterruptedDueToReplStateChange
+    13435, // NotPrimaryNoSecondaryOk
+    13436, // NotPrimaryOrSecondary
+];
+
+impl Error {
+    /// Returns the class of this error.
+    ///
+    /// Network, server selection and replica set state change errors from the driver are
+    /// `Transient`, errors converting documents into operations are `Parse` and everything else
+    /// (e.g. authentication failures) is `Fatal`.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::Database(ref err) if is_transient(err) => ErrorKind::Transient,
+            Error::Database(_) => ErrorKind::Fatal,
+            Error::MissingField(_)
+            | Error::UnknownOperation(_)
+            | Error::InvalidOperation
+            | Error::MaxDepthExceeded
+            | Error::Validation(_)
+            | Error::Deserialize(_) => ErrorKind::Parse,
+            Error::ReconnectExhausted(_) | Error::EmptyOplog | Error::Io(_) => ErrorKind::Fatal,
+        }
+    }
+
+    /// Returns whether the server killed the cursor this error was read from (`CursorNotFound`),
+    /// e.g. after it timed out or was killed by an operator, rather than the connection failing.
+    pub(crate) fn is_cursor_not_found(&self) -> bool {
+        match *self {
+            Error::Database(ref err) => match *err.kind {
+                mongodb::error::ErrorKind::Command(ref err) => err.code == CURSOR_NOT_FOUND,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether a driver error is likely temporary.
+fn is_transient(err: &mongodb::error::Error) -> bool {
+    use mongodb::error::ErrorKind::*;
+
+    match *err.kind {
+        Io(_) | ConnectionPoolCleared { .. } | ServerSelection { .. } | DnsResolve { .. } => true,
+        Command(ref err) => TRANSIENT_CODES.contains(&err.code),
+        _ => {
+            err.contains_label("RetryableWriteError")
+                || err.contains_label("TransientTransactionError")
+        }
+    }
 }
 
 impl std::error::Error for Error {
@@ -26,6 +133,12 @@ impl std::error::Error for Error {
             Error::MissingField(e) => Some(e),
             Error::UnknownOperation(_) => None,
             Error::InvalidOperation => None,
+            Error::MaxDepthExceeded => None,
+            Error::Validation(_) => None,
+            Error::Deserialize(e) => Some(e),
+            Error::ReconnectExhausted(e) => Some(e.as_ref()),
+            Error::EmptyOplog => None,
+            Error::Io(e) => Some(e),
         }
     }
 }
@@ -34,9 +147,37 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Database(ref err) => err.fmt(f),
-            Error::MissingField(ref err) => err.fmt(f),
-            Error::UnknownOperation(ref op) => write!(f, "Unknown operation type found: {}", op),
-            Error::InvalidOperation => write!(f, "Invalid operation"),
+            Error::MissingField(ref err) => match *err {
+                ValueAccessError::NotPresent => {
+                    write!(f, "oplog entry is missing a required field")
+                }
+                ValueAccessError::UnexpectedType => {
+                    write!(f, "oplog entry has a field of an unexpected type")
+                }
+                ref err => write!(f, "oplog entry has an invalid field: {}", err),
+            },
+            Error::UnknownOperation(ref op) => {
+                write!(f, "unknown oplog operation code: {:?}", op)
+            }
+            Error::InvalidOperation => write!(
+                f,
+                "invalid operation: malformed applyOps command or update diff"
+            ),
+            Error::MaxDepthExceeded => write!(
+                f,
+                "applyOps commands nested deeper than ParseOptions::max_depth"
+            ),
+            Error::Validation(ref problem) => write!(f, "invalid operation: {}", problem),
+            Error::Deserialize(ref err) => err.fmt(f),
+            Error::ReconnectExhausted(ref err) => {
+                write!(
+                    f,
+                    "failed to reconnect to the oplog after exhausting retries: {}",
+                    err
+                )
+            }
+            Error::EmptyOplog => write!(f, "the oplog is empty"),
+            Error::Io(ref err) => err.fmt(f),
         }
     }
 }
@@ -47,8 +188,134 @@ impl From<bson::document::ValueAccessError> for Error {
     }
 }
 
+impl From<bson::de::Error> for Error {
+    fn from(original: bson::de::Error) -> Error {
+        Error::Deserialize(original)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(original: std::io::Error) -> Error {
+        Error::Io(original)
+    }
+}
+
 impl From<mongodb::error::Error> for Error {
     fn from(original: mongodb::error::Error) -> Error {
         Error::Database(original)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn command_error(code: i32) -> Error {
+        let err: mongodb::error::CommandError =
+            bson::from_document(doc! { "code": code, "codeName": "", "errmsg": "" }).unwrap();
+
+        Error::Database(mongodb::error::ErrorKind::Command(err).into())
+    }
+
+    #[test]
+    fn kind_classifies_parse_errors() {
+        assert_eq!(Error::UnknownOperation("x".into()).kind(), ErrorKind::Parse);
+        assert_eq!(
+            Error::MissingField(bson::document::ValueAccessError::NotPresent).kind(),
+            ErrorKind::Parse
+        );
+        assert_eq!(Error::InvalidOperation.kind(), ErrorKind::Parse);
+        assert_eq!(Error::MaxDepthExceeded.kind(), ErrorKind::Parse);
+        assert_eq!(Error::Validation("".into()).kind(), ErrorKind::Parse);
+        assert_eq!(
+            Error::from(bson::Document::from_reader(&[5u8, 0, 0][..]).unwrap_err()).kind(),
+            ErrorKind::Parse
+        );
+    }
+
+    #[test]
+    fn kind_classifies_connection_errors_as_transient() {
+        let err = Error::from(mongodb::error::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        ));
+
+        assert_eq!(err.kind(), ErrorKind::Transient);
+    }
+
+    #[test]
+    fn kind_classifies_state_changes_as_transient() {
+        assert_eq!(command_error(189).kind(), ErrorKind::Transient);
+        assert_eq!(command_error(43).kind(), ErrorKind::Transient);
+    }
+
+    #[test]
+    fn kind_classifies_other_errors_as_fatal() {
+        assert_eq!(command_error(13).kind(), ErrorKind::Fatal);
+        assert_eq!(
+            Error::ReconnectExhausted(Box::new(command_error(189))).kind(),
+            ErrorKind::Fatal
+        );
+    }
+
+    #[test]
+    fn display_describes_parse_errors() {
+        assert_eq!(
+            Error::UnknownOperation("x".into()).to_string(),
+            "unknown oplog operation code: \"x\""
+        );
+        assert_eq!(
+            Error::MissingField(ValueAccessError::NotPresent).to_string(),
+            "oplog entry is missing a required field"
+        );
+        assert_eq!(
+            Error::MissingField(ValueAccessError::UnexpectedType).to_string(),
+            "oplog entry has a field of an unexpected type"
+        );
+        assert_eq!(
+            Error::InvalidOperation.to_string(),
+            "invalid operation: malformed applyOps command or update diff"
+        );
+        assert_eq!(
+            Error::MaxDepthExceeded.to_string(),
+            "applyOps commands nested deeper than ParseOptions::max_depth"
+        );
+    }
+
+    #[test]
+    fn display_describes_other_errors() {
+        assert_eq!(Error::EmptyOplog.to_string(), "the oplog is empty");
+        assert_eq!(
+            Error::ReconnectExhausted(Box::new(Error::EmptyOplog)).to_string(),
+            "failed to reconnect to the oplog after exhausting retries: the oplog is empty"
+        );
+    }
+
+    #[test]
+    fn source_returns_the_underlying_error() {
+        use std::error::Error as _;
+
+        let err = Error::from(bson::document::ValueAccessError::NotPresent);
+
+        assert_eq!(
+            err.source().map(ToString::to_string),
+            Some(bson::document::ValueAccessError::NotPresent.to_string())
+        );
+        assert!(Error::InvalidOperation.source().is_none());
+    }
+
+    #[test]
+    fn source_chains_through_reconnection_failures() {
+        use std::error::Error as _;
+
+        let err = Error::ReconnectExhausted(Box::new(command_error(189)));
+        let reconnect = err.source().unwrap();
+
+        assert!(reconnect.downcast_ref::<Error>().is_some());
+        assert!(reconnect
+            .source()
+            .unwrap()
+            .downcast_ref::<mongodb::error::Error>()
+            .is_some());
+    }
+}
@@ -0,0 +1,164 @@
+//! The gap module is responsible for detecting when resuming the oplog may have skipped
+//! operations because they were truncated from the oplog in the meantime.
+
+use std::collections::HashMap;
+
+use crate::Operation;
+use mongodb::bson;
+
+/// A range of the oplog a namespace may have lost operations in, as reported by `GapDetector`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// The namespace which may have lost operations.
+    pub namespace: String,
+    /// The oplog timestamp of the last operation seen in the namespace.
+    pub last_seen: bson::Timestamp,
+    /// The oplog timestamp of the earliest entry left in the oplog on resuming.
+    pub earliest: bson::Timestamp,
+}
+
+/// Tracks the last position seen in each namespace to detect gaps on resuming the oplog.
+///
+/// The oplog is a capped collection so, if a consumer falls too far behind or is down for too
+/// long, the entries after its last position are truncated before it resumes and are lost. To
+/// detect this, operations are pushed as they are processed and, whenever the oplog is resumed
+/// (e.g. after reconnecting or restarting), `check` is called with the earliest timestamp left in
+/// the oplog (see `Oplog::earliest_timestamp`). Any namespace last seen before then is reported as
+/// a `Gap` to the callback, e.g. to log a warning or trigger a full resync of the namespace.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{GapDetector, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut gaps = GapDetector::new(|gap| eprintln!("Possible gap: {:?}", gap));
+///
+/// // ...push operations from a previous run, then resume...
+/// gaps.check(Oplog::earliest_timestamp(&client).await?);
+///
+/// let mut oplog = Oplog::new(&client).await?;
+/// while let Some(res) = oplog.next().await {
+///     let oper = res?;
+///     gaps.push(&oper);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct GapDetector {
+    /// The oplog timestamp of the last operation seen in each namespace.
+    last_seen: HashMap<String, bson::Timestamp>,
+    /// Receives each gap detected.
+    on_gap: Box<dyn FnMut(&Gap) + Send>,
+}
+
+impl GapDetector {
+    /// Returns a detector reporting each gap to the given callback.
+    pub fn new<F>(on_gap: F) -> GapDetector
+    where
+        F: FnMut(&Gap) + Send + 'static,
+    {
+        GapDetector {
+            last_seen: HashMap::new(),
+            on_gap: Box::new(on_gap),
+        }
+    }
+
+    /// Record the position of an operation, including each operation contained in `ApplyOps`.
+    ///
+    /// No-ops and other operations without a namespace are ignored.
+    pub fn push(&mut self, operation: &Operation) {
+        for operation in operation.flatten() {
+            if let Some(namespace) = operation.namespace().filter(|ns| !ns.is_empty()) {
+                self.last_seen
+                    .insert(namespace.to_string(), operation.oplog_ts());
+            }
+        }
+    }
+
+    /// Returns the oplog timestamp of the last operation seen in the given namespace, if any.
+    pub fn last_seen(&self, namespace: &str) -> Option<bson::Timestamp> {
+        self.last_seen.get(namespace).copied()
+    }
+
+    /// Check for gaps on resuming an oplog whose earliest entry has the given timestamp,
+    /// returning the number found.
+    ///
+    /// Each namespace last seen before the earliest entry is reported to the callback, in order of
+    /// their last positions. A namespace last seen at the earliest entry itself has lost nothing.
+    pub fn check(&mut self, earliest: bson::Timestamp) -> usize {
+        let mut gaps: Vec<Gap> = self
+            .last_seen
+            .iter()
+            .filter(|&(_, &last_seen)| last_seen < earliest)
+            .map(|(namespace, &last_seen)| Gap {
+                namespace: namespace.clone(),
+                last_seen,
+                earliest,
+            })
+            .collect();
+        gaps.sort_by(|a, b| (a.last_seen, &a.namespace).cmp(&(b.last_seen, &b.namespace)));
+
+        for gap in &gaps {
+            (self.on_gap)(gap);
+        }
+
+        gaps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson};
+    use std::sync::{Arc, Mutex};
+
+    fn insert(ns: &str, time: u32) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time, increment: 0 }),
+            "op" : "i",
+            "ns" : ns,
+            "o" : { "_id" : 1 }
+        })
+        .unwrap()
+    }
+
+    fn ts(time: u32) -> bson::Timestamp {
+        bson::Timestamp { time, increment: 0 }
+    }
+
+    #[test]
+    fn gap_detector_reports_namespaces_last_seen_before_the_oplog_window() {
+        let gaps = Arc::new(Mutex::new(Vec::new()));
+        let sink = gaps.clone();
+        let mut detector = GapDetector::new(move |gap| sink.lock().unwrap().push(gap.clone()));
+
+        detector.push(&insert("foo.bar", 10));
+        detector.push(&insert("foo.baz", 20));
+        detector.push(&insert("foo.qux", 30));
+
+        assert_eq!(detector.check(ts(20)), 1);
+        assert_eq!(
+            *gaps.lock().unwrap(),
+            vec![Gap {
+                namespace: "foo.bar".into(),
+                last_seen: ts(10),
+                earliest: ts(20),
+            }]
+        );
+    }
+
+    #[test]
+    fn gap_detector_reports_nothing_within_the_oplog_window() {
+        let mut detector = GapDetector::new(|_| panic!("Unexpected gap."));
+
+        detector.push(&insert("foo.bar", 10));
+
+        assert_eq!(detector.check(ts(5)), 0);
+        assert_eq!(detector.last_seen("foo.bar"), Some(ts(10)));
+        assert_eq!(detector.last_seen("foo.baz"), None);
+    }
+}
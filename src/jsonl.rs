@@ -0,0 +1,72 @@
+//! The jsonl module is responsible for writing operations as newline-delimited JSON (JSON Lines),
+//! e.g. to dump an oplog to a file for debugging.
+
+use std::io::{self, Write};
+
+use crate::Operation;
+
+/// Write an operation to the given writer as a single line of JSON using its `serde`
+/// representation.
+///
+/// The writer is flushed after each line so the output can be followed as it is written (e.g.
+/// with `tail -f`). This requires the `jsonl` feature.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::Oplog;
+/// use std::io;
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut oplog = Oplog::new(&client).await?;
+/// let mut stdout = io::stdout();
+///
+/// while let Some(res) = oplog.next().await {
+///     oplog::write_jsonl(&res?, &mut stdout)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_jsonl<W: Write>(operation: &Operation, writer: &mut W) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, operation)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson};
+    use mongodb::bson;
+
+    fn insert(id: i32) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 0 }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : id }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn write_jsonl_writes_one_line_per_operation() {
+        let mut output = Vec::new();
+
+        for id in 1..=3 {
+            write_jsonl(&insert(id), &mut output).unwrap();
+        }
+
+        let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        for (line, id) in lines.iter().zip(1..) {
+            let operation: Operation = serde_json::from_str(line).unwrap();
+
+            assert_eq!(operation, insert(id));
+        }
+    }
+}
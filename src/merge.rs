@@ -0,0 +1,272 @@
+//! The merge module is responsible for combining the oplogs of every shard of a sharded cluster
+//! into a single stream ordered by oplog timestamp.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Future, Stream, StreamExt};
+use mongodb::Client;
+use tokio::time::Sleep;
+
+use crate::{Clock, Operation, Oplog, OplogBuilder, Result, SystemClock};
+
+/// The oplog of a single shard together with its next operation, if read.
+struct Shard {
+    /// The oplog of the shard.
+    oplog: Oplog,
+    /// The next operation of the shard, buffered until it is the earliest of all shards.
+    head: Option<Operation>,
+    /// The position of the last operation read from the shard, if any.
+    position: Option<(u32, u32)>,
+    /// Whether the oplog of the shard has ended.
+    done: bool,
+}
+
+/// A stream merging the oplogs of several shards into a single stream ordered by oplog timestamp.
+///
+/// Each shard's oplog is tailed separately and the operation with the earliest position (see
+/// `Operation::sort_key`) across all shards is yielded next, i.e. a k-way merge. This requires the
+/// next operation from every shard, or for a shard to have already yielded a later operation, so
+/// an idle shard would hold back the others indefinitely. Instead, operations are buffered for at
+/// most `max_skew` after they were written while waiting for stalled shards, after which they are
+/// yielded anyway and a stalled shard's operations may arrive out of order.
+///
+/// Idle primaries write a no-op to their oplog every 10 seconds so shards should be tailed with
+/// `OplogBuilder::heartbeats` enabled (when filtering) to keep every shard advancing. Errors from
+/// any shard are yielded as they occur.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{MergedOplog, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let shards = vec![
+///     Client::with_uri_str("mongodb://shard0").await?,
+///     Client::with_uri_str("mongodb://shard1").await?,
+/// ];
+///
+/// let mut oplog = MergedOplog::connect(&shards, Oplog::builder().heartbeats(true)).await?;
+///
+/// while let Some(res) = oplog.next().await {
+///     println!("{}", res?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct MergedOplog {
+    /// The oplogs of every shard.
+    shards: Vec<Shard>,
+    /// The longest an operation is buffered after it was written while waiting for stalled
+    /// shards.
+    max_skew: Duration,
+    /// Wakes the stream when the earliest buffered operation exceeds the maximum skew.
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// The source of the current time for measuring how long operations have been buffered.
+    clock: Arc<dyn Clock>,
+}
+
+impl MergedOplog {
+    /// Returns a stream merging the given oplogs, one per shard.
+    pub fn new(oplogs: Vec<Oplog>) -> MergedOplog {
+        MergedOplog {
+            shards: oplogs
+                .into_iter()
+                .map(|oplog| Shard {
+                    oplog,
+                    head: None,
+                    position: None,
+                    done: false,
+                })
+                .collect(),
+            max_skew: Duration::from_secs(30),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Tail the oplog of each of the given shards with the same builder and merge them.
+    pub async fn connect(clients: &[Client], builder: OplogBuilder) -> Result<MergedOplog> {
+        let mut oplogs = Vec::with_capacity(clients.len());
+        for client in clients {
+            oplogs.push(builder.clone().build(client).await?);
+        }
+
+        Ok(MergedOplog::new(oplogs))
+    }
+
+    /// Set the longest an operation is buffered after it was written while waiting for stalled
+    /// shards.
+    ///
+    /// This is measured by the operation's wall clock time (or its timestamp on servers predating
+    /// it) so should allow for clock skew between the shards. Defaults to 30 seconds.
+    pub fn max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Measure how long operations have been buffered against the given clock.
+    ///
+    /// By default, this is the system time (see `SystemClock`).
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl Stream for MergedOplog {
+    type Item = Result<Operation>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        for shard in this.shards.iter_mut() {
+            if shard.head.is_some() || shard.done {
+                continue;
+            }
+
+            match shard.oplog.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(operation))) => {
+                    shard.position = Some(operation.sort_key());
+                    shard.head = Some(operation);
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => shard.done = true,
+                Poll::Pending => {}
+            }
+        }
+
+        let earliest = this
+            .shards
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shard)| shard.head.as_ref().map(|head| (index, head)))
+            .min_by_key(|&(_, head)| head.sort_key());
+
+        let (index, head) = match earliest {
+            Some(earliest) => earliest,
+            None if this.shards.iter().all(|shard| shard.done) => return Poll::Ready(None),
+            None => return Poll::Pending,
+        };
+
+        // A shard without a buffered operation can only yield ones after its last position.
+        let key = head.sort_key();
+        let ordered = this.shards.iter().all(|shard| {
+            shard.head.is_some() || shard.done || shard.position.is_some_and(|at| at >= key)
+        });
+
+        if !ordered {
+            let written = head
+                .metadata()
+                .wall_clock
+                .unwrap_or_else(|| head.timestamp());
+            let waited = (this.clock.now() - written).to_std().unwrap_or_default();
+
+            if let Some(remaining) = this.max_skew.checked_sub(waited) {
+                if !remaining.is_zero() {
+                    let deadline = this
+                        .deadline
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep(remaining)));
+                    deadline
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + remaining);
+
+                    if deadline.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+
+        this.deadline = None;
+        Poll::Ready(this.shards[index].head.take().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+    use bson::{doc, Document};
+    use chrono::TimeZone;
+    use futures::stream;
+    use mongodb::bson;
+
+    fn insert(time: u32, increment: u32) -> mongodb::error::Result<Document> {
+        Ok(doc! {
+            "ts": bson::Timestamp { time, increment },
+            "op": "i",
+            "ns": "foo.bar",
+            "o": { "_id": increment }
+        })
+    }
+
+    fn positions(operations: &[Operation]) -> Vec<(u32, u32)> {
+        operations.iter().map(Operation::sort_key).collect()
+    }
+
+    #[tokio::test]
+    async fn merged_oplog_orders_operations_across_shards() {
+        let shard0 =
+            Oplog::from_documents(stream::iter(vec![insert(1, 0), insert(3, 0), insert(3, 2)]));
+        let shard1 =
+            Oplog::from_documents(stream::iter(vec![insert(2, 0), insert(3, 1), insert(4, 0)]));
+
+        let operations: Vec<Operation> = MergedOplog::new(vec![shard0, shard1])
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(
+            positions(&operations),
+            vec![(1, 0), (2, 0), (3, 0), (3, 1), (3, 2), (4, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn merged_oplog_waits_for_stalled_shards_up_to_the_max_skew() {
+        let clock = MockClock::new(chrono::Utc.timestamp_opt(1479561394, 0).unwrap());
+        let shard0 = Oplog::from_documents(stream::iter(vec![insert(1479561394, 0)]));
+        let shard1 = Oplog::from_documents(stream::pending());
+        let mut oplog = MergedOplog::new(vec![shard0, shard1])
+            .max_skew(Duration::from_secs(30))
+            .clock(clock.clone());
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(oplog.poll_next_unpin(&mut cx).is_pending());
+        clock.advance(chrono::Duration::seconds(29));
+        assert!(oplog.poll_next_unpin(&mut cx).is_pending());
+        clock.advance(chrono::Duration::seconds(1));
+
+        match oplog.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(Ok(operation))) => assert_eq!(operation.sort_key(), (1479561394, 0)),
+            _ => panic!("Expected operation."),
+        }
+    }
+
+    #[tokio::test]
+    async fn merged_oplog_yields_operations_older_than_the_max_skew() {
+        let clock = MockClock::new(chrono::Utc.timestamp_opt(1479561394 + 60, 0).unwrap());
+        let shard0 = Oplog::from_documents(stream::iter(vec![insert(1479561394, 0)]));
+        let shard1 = Oplog::from_documents(stream::pending());
+        let mut oplog = MergedOplog::new(vec![shard0, shard1]).clock(clock);
+
+        let operation = oplog.next().await.unwrap().unwrap();
+
+        assert_eq!(operation.sort_key(), (1479561394, 0));
+    }
+
+    #[tokio::test]
+    async fn merged_oplog_ends_when_every_shard_ends() {
+        let shard0 = Oplog::from_documents(stream::iter(vec![insert(1, 0)]));
+        let shard1 = Oplog::from_documents(stream::iter(vec![]));
+        let mut oplog = MergedOplog::new(vec![shard0, shard1]);
+
+        assert!(oplog.next().await.unwrap().is_ok());
+        assert!(oplog.next().await.is_none());
+    }
+}
@@ -0,0 +1,321 @@
+//! The metadata module is responsible for extracting the optional fields MongoDB attaches to oplog
+//! entries regardless of their operation type (e.g. transaction numbers for retryable writes).
+
+use crate::Result;
+use bson::document::ValueAccessError;
+use bson::{doc, Bson, Document};
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Optional metadata shared by all kinds of oplog operation.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata {
+    /// The version of the oplog entry format (`v`), e.g. 2.
+    pub version: Option<i32>,
+    /// The unique 64-bit hash of the operation (`h`).
+    ///
+    /// This is only written by servers using replication protocol version 0 (before MongoDB 4.2)
+    /// so is `None` in newer oplogs.
+    pub hash: Option<i64>,
+    /// The transaction number of a retryable write or multi-document transaction.
+    pub txn_number: Option<i64>,
+    /// The statement identifier of this operation within its transaction or retryable write.
+    pub stmt_id: Option<StmtId>,
+    /// The identifier of the session of this operation, if any.
+    pub session_id: Option<bson::Uuid>,
+    /// The timestamp of the previous oplog entry written by the same transaction, if any.
+    pub prev_op_time: Option<bson::Timestamp>,
+    /// The timestamp of the oplog entry recording the pre-image of this operation, if any.
+    ///
+    /// This is written for `findAndModify` operations by servers storing their pre-images in the
+    /// oplog and can be resolved with `Operation::resolve_pre_image`.
+    pub pre_image_op_time: Option<bson::Timestamp>,
+    /// The wall clock time of the operation as recorded by MongoDB 3.6+.
+    ///
+    /// This may differ from the operation's logical `timestamp`.
+    pub wall_clock: Option<DateTime<Utc>>,
+    /// Whether the operation was written by a chunk migration between shards rather than by a
+    /// client.
+    pub from_migrate: bool,
+}
+
+/// The statement identifiers (`stmtId`) of an operation.
+///
+/// This is usually a single statement but batched retryable inserts record the statements of
+/// several documents in a single oplog entry as an array.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StmtId {
+    /// The identifier of a single statement.
+    Single(i64),
+    /// The identifiers of the statements of a batched write in order.
+    Batch(Vec<i64>),
+}
+
+impl StmtId {
+    /// Returns whether the given statement is one of these, e.g. to check whether a retried
+    /// statement has already been written.
+    pub fn contains(&self, stmt_id: i64) -> bool {
+        match *self {
+            StmtId::Single(id) => id == stmt_id,
+            StmtId::Batch(ref ids) => ids.contains(&stmt_id),
+        }
+    }
+}
+
+impl Metadata {
+    /// Try to extract the metadata from an oplog document.
+    pub(crate) fn new(document: &Document) -> Result<Metadata> {
+        Ok(Metadata {
+            version: get_i32(document, "v")?,
+            hash: get_i64(document, "h")?,
+            txn_number: get_i64(document, "txnNumber")?,
+            stmt_id: get_stmt_id(document)?,
+            session_id: get_session_id(document)?,
+            prev_op_time: get_op_time(document, "prevOpTime")?,
+            pre_image_op_time: get_op_time(document, "preImageOpTime")?,
+            wall_clock: get_wall_clock(document)?,
+            from_migrate: get_bool(document, "fromMigrate")?,
+        })
+    }
+
+    /// Write the metadata back into an oplog document, except for the session which is written
+    /// together with the uid of the operation in its `lsid`.
+    pub(crate) fn write_to(&self, document: &mut Document) {
+        if let Some(version) = self.version {
+            document.insert("v", version);
+        }
+        if let Some(hash) = self.hash {
+            document.insert("h", hash);
+        }
+        if let Some(txn_number) = self.txn_number {
+            document.insert("txnNumber", txn_number);
+        }
+        match self.stmt_id {
+            Some(StmtId::Single(stmt_id)) => {
+                document.insert("stmtId", stmt_id);
+            }
+            Some(StmtId::Batch(ref stmt_ids)) => {
+                document.insert("stmtId", stmt_ids.clone());
+            }
+            None => {}
+        }
+        if let Some(ts) = self.prev_op_time {
+            document.insert("prevOpTime", doc! { "ts": ts });
+        }
+        if let Some(ts) = self.pre_image_op_time {
+            document.insert("preImageOpTime", doc! { "ts": ts });
+        }
+        if let Some(wall) = self.wall_clock {
+            document.insert("wall", bson::DateTime::from_millis(wall.timestamp_millis()));
+        }
+        if self.from_migrate {
+            document.insert("fromMigrate", true);
+        }
+    }
+}
+
+/// Return the UUID identifying the session in the `lsid` of a given document.
+fn get_session_id(document: &Document) -> Result<Option<bson::Uuid>> {
+    match document
+        .get_document("lsid")
+        .ok()
+        .and_then(|lsid| lsid.get("id"))
+    {
+        None => Ok(None),
+        Some(Bson::Binary(binary)) => binary
+            .to_uuid()
+            .map(Some)
+            .map_err(|_| ValueAccessError::UnexpectedType.into()),
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+/// Return the statement identifiers of a given document, either a single integer or an array.
+fn get_stmt_id(document: &Document) -> Result<Option<StmtId>> {
+    match document.get("stmtId") {
+        Some(Bson::Array(ids)) => ids
+            .iter()
+            .map(|id| match *id {
+                Bson::Int32(id) => Ok(id.into()),
+                Bson::Int64(id) => Ok(id),
+                _ => Err(ValueAccessError::UnexpectedType.into()),
+            })
+            .collect::<Result<Vec<i64>>>()
+            .map(|ids| Some(StmtId::Batch(ids))),
+        _ => Ok(get_i64(document, "stmtId")?.map(StmtId::Single)),
+    }
+}
+
+/// Return the timestamp of an optime field (e.g. `prevOpTime`) of a given document.
+fn get_op_time(document: &Document, key: &str) -> Result<Option<bson::Timestamp>> {
+    match document.get(key) {
+        None => Ok(None),
+        Some(Bson::Document(op_time)) => Ok(Some(op_time.get_timestamp("ts")?)),
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+/// Return the `wall` clock time of a given document.
+fn get_wall_clock(document: &Document) -> Result<Option<DateTime<Utc>>> {
+    match document.get("wall") {
+        None => Ok(None),
+        Some(Bson::DateTime(wall)) => {
+            Ok(Utc.timestamp_millis_opt(wall.timestamp_millis()).single())
+        }
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+/// Return an optional boolean field, defaulting to false.
+fn get_bool(document: &Document, key: &str) -> Result<bool> {
+    match document.get(key) {
+        None => Ok(false),
+        Some(&Bson::Boolean(value)) => Ok(value),
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+/// Return an optional 32-bit integer field.
+fn get_i32(document: &Document, key: &str) -> Result<Option<i32>> {
+    match document.get_i32(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ValueAccessError::NotPresent) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Return an optional integer field, accepting both 32-bit and 64-bit integers.
+fn get_i64(document: &Document, key: &str) -> Result<Option<i64>> {
+    match document.get(key) {
+        None => Ok(None),
+        Some(&Bson::Int32(value)) => Ok(Some(value.into())),
+        Some(&Bson::Int64(value)) => Ok(Some(value)),
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use bson::doc;
+
+    #[test]
+    fn metadata_defaults_to_empty() {
+        let metadata = Metadata::new(&doc! { "op" : "i" }).unwrap();
+
+        assert_eq!(metadata, Metadata::default());
+    }
+
+    #[test]
+    fn metadata_parses_transaction_fields() {
+        let metadata = Metadata::new(&doc! { "txnNumber" : 5i64, "stmtId" : 0 }).unwrap();
+
+        assert_eq!(metadata.txn_number, Some(5));
+        assert_eq!(metadata.stmt_id, Some(StmtId::Single(0)));
+    }
+
+    #[test]
+    fn metadata_parses_batched_statement_ids() {
+        let metadata =
+            Metadata::new(&doc! { "txnNumber" : 5i64, "stmtId" : [0, 1, 2i64] }).unwrap();
+
+        assert_eq!(metadata.stmt_id, Some(StmtId::Batch(vec![0, 1, 2])));
+        assert!(metadata.stmt_id.unwrap().contains(2));
+    }
+
+    #[test]
+    fn metadata_rejects_invalid_statement_ids() {
+        match Metadata::new(&doc! { "stmtId" : ["0"] }) {
+            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::UnexpectedType),
+            _ => panic!("Expected missing field."),
+        }
+    }
+
+    #[test]
+    fn metadata_parses_session_and_previous_op_time() {
+        let session_id = bson::Uuid::new();
+        let metadata = Metadata::new(&doc! {
+            "lsid" : { "id" : session_id },
+            "prevOpTime" : {
+                "ts" : bson::Timestamp { time: 1, increment: 2 },
+                "t" : 1i64
+            }
+        })
+        .unwrap();
+
+        assert_eq!(metadata.session_id, Some(session_id));
+        assert_eq!(
+            metadata.prev_op_time,
+            Some(bson::Timestamp {
+                time: 1,
+                increment: 2
+            })
+        );
+    }
+
+    #[test]
+    fn metadata_parses_the_pre_image_op_time() {
+        let metadata = Metadata::new(&doc! {
+            "preImageOpTime" : {
+                "ts" : bson::Timestamp { time: 1, increment: 1 },
+                "t" : 1i64
+            }
+        })
+        .unwrap();
+
+        assert_eq!(
+            metadata.pre_image_op_time,
+            Some(bson::Timestamp {
+                time: 1,
+                increment: 1
+            })
+        );
+    }
+
+    #[test]
+    fn metadata_parses_wall_clock_times() {
+        let metadata = Metadata::new(&doc! {
+            "wall" : bson::DateTime::from_millis(1479561394123)
+        })
+        .unwrap();
+
+        assert_eq!(
+            metadata.wall_clock,
+            Some(Utc.timestamp_millis_opt(1479561394123).unwrap())
+        );
+    }
+
+    #[test]
+    fn metadata_parses_the_version() {
+        let metadata = Metadata::new(&doc! { "v" : 2 }).unwrap();
+
+        assert_eq!(metadata.version, Some(2));
+    }
+
+    #[test]
+    fn metadata_parses_the_hash() {
+        let metadata = Metadata::new(&doc! { "h" : (-1742072865587022793i64) }).unwrap();
+
+        assert_eq!(metadata.hash, Some(-1742072865587022793));
+    }
+
+    #[test]
+    fn metadata_parses_from_migrate() {
+        let metadata = Metadata::new(&doc! { "fromMigrate" : true }).unwrap();
+
+        assert!(metadata.from_migrate);
+    }
+
+    #[test]
+    fn metadata_returns_unexpected_types() {
+        match Metadata::new(&doc! { "txnNumber" : "5" }) {
+            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::UnexpectedType),
+            _ => panic!("Expected missing field."),
+        }
+    }
+}
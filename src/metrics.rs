@@ -0,0 +1,74 @@
+//! The metrics module is responsible for the hooks an `Oplog` calls as it reads operations so
+//! consumers can record counters and replication lag without wrapping the stream themselves.
+
+use std::time::Duration;
+
+use crate::{Error, Operation};
+use mongodb::bson;
+
+/// Callbacks invoked by an `Oplog` as it reads operations.
+///
+/// Every method does nothing by default so implementations need only override those they are
+/// interested in. Callbacks are invoked synchronously while polling so should be cheap (e.g.
+/// incrementing an atomic counter).
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use mongodb::Client;
+/// use oplog::{Error, Oplog, OplogMetrics, Operation};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     operations: AtomicUsize,
+///     errors: AtomicUsize,
+/// }
+///
+/// impl OplogMetrics for Counters {
+///     fn on_operation(&self, _operation: &Operation) {
+///         self.operations.fetch_add(1, Ordering::Relaxed);
+///     }
+///
+///     fn on_error(&self, _error: &Error) {
+///         self.errors.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+///
+/// let mut oplog = Oplog::builder()
+///     .metrics(Counters::default())
+///     .build(&client)
+///     .await?;
+///
+/// # Ok(())
+/// # }
+/// ```
+pub trait OplogMetrics: Send + Sync {
+    /// Called with each operation successfully read from the oplog.
+    fn on_operation(&self, _operation: &Operation) {}
+
+    /// Called with each error yielded by the oplog.
+    fn on_error(&self, _error: &Error) {}
+
+    /// Called with the lag of each operation read from the oplog, i.e. how long ago it was
+    /// written as measured by its wall clock time (or its timestamp on servers predating it).
+    fn on_lag(&self, _lag: Duration) {}
+
+    /// Called when the server killed the cursor on the oplog (`CursorNotFound`) and it is being
+    /// reopened after the given timestamp of the last document read, if any.
+    ///
+    /// This is not an error and the oplog continues without yielding one, e.g. log it at an info
+    /// level.
+    fn on_cursor_reopened(&self, _resume_after: Option<bson::Timestamp>) {}
+}
+
+/// Metrics which discard every callback.
+///
+/// This is the default for an `Oplog` without `OplogBuilder::metrics`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl OplogMetrics for NoopMetrics {}
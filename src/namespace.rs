@@ -0,0 +1,379 @@
+//! The namespace module is responsible for turning namespace patterns into server-side conditions
+//! on the `ns` field of the oplog.
+
+use bson::{doc, Bson, Document};
+use mongodb::bson;
+
+/// A filter on the namespaces of operations, applied as part of the oplog query.
+///
+/// A filter is either an allow-list, only returning operations in the namespaces given, or a
+/// deny-list, returning operations in every namespace but those given. Namespaces are given as
+/// exact namespaces (e.g. `db.collection`), database prefixes (e.g. `db.*`), globs (e.g.
+/// `sales.orders_*`, as with `Operation::namespace_matches`) or regular expressions matching the
+/// full namespace (e.g. `^sales\.`), and any number may be combined.
+///
+/// A single pattern converts into an allow-list so `OplogBuilder::namespace_filter` also accepts
+/// a `&str`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use mongodb::Client;
+/// use oplog::{NamespaceFilter, Oplog};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+///
+/// let mut oplog = Oplog::builder()
+///     .namespace_filter(
+///         NamespaceFilter::allow()
+///             .namespace("crm.customers")
+///             .namespace("billing.*")
+///             .regex("^sales\\.orders_"),
+///     )
+///     .build(&client)
+///     .await?;
+///
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamespaceFilter {
+    /// Whether operations in the matching namespaces are excluded rather than returned.
+    deny: bool,
+    /// The patterns matching namespaces.
+    patterns: Vec<Pattern>,
+}
+
+/// A pattern matching namespaces in a `NamespaceFilter`.
+#[derive(Clone, Debug, PartialEq)]
+enum Pattern {
+    /// An exact namespace, a database prefix or a glob, e.g. `db.collection`, `db.*` or `*.orders`.
+    Namespace(String),
+    /// A regular expression matching the full namespace.
+    Regex(String),
+}
+
+impl NamespaceFilter {
+    /// Returns an empty allow-list matching no namespaces until some are added.
+    pub fn allow() -> NamespaceFilter {
+        NamespaceFilter {
+            deny: false,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Returns an empty deny-list matching every namespace until some are excluded.
+    pub fn deny() -> NamespaceFilter {
+        NamespaceFilter {
+            deny: true,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Add an exact namespace (e.g. `db.collection`), every collection in a database (e.g.
+    /// `db.*`) or the namespaces matching a glob (e.g. `*.orders`) to the filter.
+    pub fn namespace(mut self, pattern: &str) -> Self {
+        self.patterns.push(Pattern::Namespace(pattern.into()));
+        self
+    }
+
+    /// Add the namespaces matching a regular expression (e.g. `^sales\.`) to the filter.
+    ///
+    /// The expression is evaluated by the server so uses its syntax (PCRE).
+    pub fn regex(mut self, pattern: &str) -> Self {
+        self.patterns.push(Pattern::Regex(pattern.into()));
+        self
+    }
+
+    /// Returns the condition on the `ns` field applying this filter, if any.
+    pub(crate) fn condition(&self) -> Option<Document> {
+        let mut conditions: Vec<Document> = self
+            .patterns
+            .iter()
+            .map(|pattern| match *pattern {
+                Pattern::Namespace(ref pattern) => condition(pattern),
+                Pattern::Regex(ref pattern) => doc! { "ns": { "$regex": pattern } },
+            })
+            .collect();
+
+        match (self.deny, conditions.len()) {
+            (true, 0) => None,
+            (true, _) => Some(doc! { "$nor": conditions }),
+            (false, 0) => Some(doc! { "ns": { "$in": [] } }),
+            (false, 1) => conditions.pop(),
+            (false, _) => Some(doc! { "$or": conditions }),
+        }
+    }
+
+    /// Returns the databases explicitly allowed by exact namespaces or database prefixes.
+    pub(crate) fn databases(&self) -> Vec<&str> {
+        if self.deny {
+            return Vec::new();
+        }
+
+        self.patterns
+            .iter()
+            .filter_map(|pattern| match *pattern {
+                Pattern::Namespace(ref pattern) => pattern.split('.').next(),
+                Pattern::Regex(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl From<&str> for NamespaceFilter {
+    fn from(pattern: &str) -> NamespaceFilter {
+        NamespaceFilter::allow().namespace(pattern)
+    }
+}
+
+/// Returns a condition on the `ns` field matching the given pattern.
+///
+/// A pattern ending in `.*` (e.g. `db.*`) matches every collection in that database, any other
+/// pattern containing `*` is a glob (see `glob_matches`) and any other pattern matches the
+/// namespace exactly.
+pub(crate) fn condition(pattern: &str) -> Document {
+    match pattern.strip_suffix(".*") {
+        Some(database) if !database.contains('*') => {
+            doc! { "ns": { "$regex": format!("^{}\\.", escape(database)) } }
+        }
+        _ if pattern.contains('*') => doc! { "ns": { "$regex": glob_regex(pattern) } },
+        _ => doc! { "ns": pattern },
+    }
+}
+
+/// Returns whether a namespace matches a glob pattern such as `sales.orders_*` or `*.orders`.
+///
+/// A `*` matches any run of characters within its segment, the database before the first `.` or
+/// the collection after it, so `sales.*` matches every collection in `sales` (including those
+/// with dots in their names) but `*.orders` does not match `sales.archive.orders`.
+pub(crate) fn glob_matches(pattern: &str, namespace: &str) -> bool {
+    match (pattern.split_once('.'), namespace.split_once('.')) {
+        (Some((database, collection)), Some((ns_database, ns_collection))) => {
+            wildcard_matches(database, ns_database) && wildcard_matches(collection, ns_collection)
+        }
+        (None, None) => wildcard_matches(pattern, namespace),
+        _ => false,
+    }
+}
+
+/// Returns a regular expression matching the same namespaces as a glob pattern.
+fn glob_regex(pattern: &str) -> String {
+    let segment = |segment: &str, wildcard: &str| {
+        segment
+            .split('*')
+            .map(escape)
+            .collect::<Vec<_>>()
+            .join(wildcard)
+    };
+
+    match pattern.split_once('.') {
+        Some((database, collection)) => format!(
+            "^{}\\.{}$",
+            segment(database, "[^.]*"),
+            segment(collection, ".*")
+        ),
+        None => format!("^{}$", segment(pattern, "[^.]*")),
+    }
+}
+
+/// Returns whether text matches a pattern in which `*` matches any run of characters.
+fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern and of the text it was matched against.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            t = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Databases used internally by MongoDB.
+const SYSTEM_DATABASES: &[&str] = &["admin", "config", "local"];
+
+/// Returns a condition on the `ns` field excluding system namespaces.
+///
+/// This excludes the `admin`, `config` and `local` databases and any `system.*` collection but
+/// not `admin.$cmd` (where transactions are recorded) nor the given databases so they can be
+/// explicitly opted in to.
+pub(crate) fn exclude_system(included: &[&str]) -> Document {
+    let databases: Vec<String> = SYSTEM_DATABASES
+        .iter()
+        .filter(|database| !included.contains(database))
+        .map(|&database| match database {
+            "admin" => "admin\\.(?!\\$cmd$)".to_string(),
+            database => format!("{}\\.", database),
+        })
+        .collect();
+
+    let pattern = if databases.is_empty() {
+        "\\.system\\.".to_string()
+    } else {
+        format!("^({})|\\.system\\.", databases.join("|"))
+    };
+
+    doc! {
+        "ns": {
+            "$not": Bson::RegularExpression(bson::Regex {
+                pattern,
+                options: String::new(),
+            })
+        }
+    }
+}
+
+/// Escape any regular expression metacharacters in the given string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn condition_matches_exact_namespaces() {
+        assert_eq!(condition("foo.bar"), doc! { "ns": "foo.bar" });
+    }
+
+    #[test]
+    fn condition_matches_globs() {
+        assert_eq!(
+            condition("sales.orders_*"),
+            doc! { "ns": { "$regex": "^sales\\.orders_.*$" } }
+        );
+        assert_eq!(
+            condition("*.orders"),
+            doc! { "ns": { "$regex": "^[^.]*\\.orders$" } }
+        );
+    }
+
+    #[test]
+    fn glob_matches_wildcards_within_segments() {
+        assert!(glob_matches("sales.*", "sales.orders"));
+        assert!(glob_matches("sales.*", "sales.orders.archive"));
+        assert!(!glob_matches("sales.*", "crm.orders"));
+        assert!(glob_matches("*.orders", "sales.orders"));
+        assert!(!glob_matches("*.orders", "sales.archive.orders"));
+        assert!(glob_matches("sales.orders_*_20*", "sales.orders_eu_2024"));
+        assert!(!glob_matches("sales.orders_*", "sales.order"));
+    }
+
+    #[test]
+    fn condition_matches_database_prefixes() {
+        assert_eq!(condition("foo.*"), doc! { "ns": { "$regex": "^foo\\." } });
+    }
+
+    fn excluded(included: &[&str]) -> String {
+        match exclude_system(included)
+            .get_document("ns")
+            .unwrap()
+            .get("$not")
+        {
+            Some(Bson::RegularExpression(regex)) => regex.pattern.clone(),
+            _ => panic!("Expected regular expression."),
+        }
+    }
+
+    #[test]
+    fn exclude_system_excludes_system_databases_and_collections() {
+        assert_eq!(
+            excluded(&[]),
+            "^(admin\\.(?!\\$cmd$)|config\\.|local\\.)|\\.system\\."
+        );
+    }
+
+    #[test]
+    fn exclude_system_allows_opting_in_to_a_system_database() {
+        assert_eq!(
+            excluded(&["config"]),
+            "^(admin\\.(?!\\$cmd$)|local\\.)|\\.system\\."
+        );
+        assert_eq!(
+            excluded(&["foo"]),
+            "^(admin\\.(?!\\$cmd$)|config\\.|local\\.)|\\.system\\."
+        );
+    }
+
+    #[test]
+    fn condition_escapes_database_names() {
+        assert_eq!(
+            condition("fo+o.*"),
+            doc! { "ns": { "$regex": "^fo\\+o\\." } }
+        );
+    }
+
+    #[test]
+    fn namespace_filter_combines_exact_and_prefix_namespaces() {
+        let filter = NamespaceFilter::allow()
+            .namespace("foo.bar")
+            .namespace("baz.*");
+
+        assert_eq!(
+            filter.condition(),
+            Some(doc! { "$or": [{ "ns": "foo.bar" }, { "ns": { "$regex": "^baz\\." } }] })
+        );
+        assert_eq!(filter.databases(), vec!["foo", "baz"]);
+    }
+
+    #[test]
+    fn namespace_filter_matches_regular_expressions() {
+        assert_eq!(
+            NamespaceFilter::allow().regex("^sales\\.").condition(),
+            Some(doc! { "ns": { "$regex": "^sales\\." } })
+        );
+    }
+
+    #[test]
+    fn namespace_filter_denies_namespaces() {
+        let filter = NamespaceFilter::deny().namespace("foo.bar").regex("^tmp_");
+
+        assert_eq!(
+            filter.condition(),
+            Some(doc! { "$nor": [{ "ns": "foo.bar" }, { "ns": { "$regex": "^tmp_" } }] })
+        );
+        assert!(filter.databases().is_empty());
+    }
+
+    #[test]
+    fn namespace_filter_without_namespaces() {
+        assert_eq!(
+            NamespaceFilter::allow().condition(),
+            Some(doc! { "ns": { "$in": [] } })
+        );
+        assert_eq!(NamespaceFilter::deny().condition(), None);
+    }
+
+    #[test]
+    fn namespace_filter_converts_single_patterns() {
+        assert_eq!(
+            NamespaceFilter::from("foo.*").condition(),
+            Some(condition("foo.*"))
+        );
+    }
+}
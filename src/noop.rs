@@ -0,0 +1,75 @@
+//! The noop module is responsible for classifying the messages of no-op operations (e.g. those
+//! written by replica set state changes) so consumers need not match on their strings.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The kind of an `Operation::Noop` as determined by its message.
+///
+/// This is returned by `Operation::noop_kind`.
+///
+/// # Example
+///
+/// ```
+/// use oplog::bson::{self, doc, Bson};
+/// use oplog::{NoopKind, Operation};
+///
+/// let document = doc! {
+///     "ts": Bson::Timestamp(bson::Timestamp {
+///         time: 1479561394,
+///         increment: 0,
+///     }),
+///     "op": "n",
+///     "ns": "",
+///     "o": { "msg": "new primary" }
+/// };
+/// let operation = Operation::new(&document)?;
+///
+/// assert_eq!(operation.noop_kind(), Some(NoopKind::NewPrimary));
+/// # Ok::<(), oplog::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NoopKind {
+    /// The initiation of the replica set (`"initiating set"`).
+    InitiatingSet,
+    /// The election of a new primary (`"new primary"`).
+    NewPrimary,
+    /// The reconfiguration of the replica set (`"Reconfig set"`).
+    ReconfigSet,
+    /// The write made to the oplog of an idle primary every 10 seconds (`"periodic noop"`).
+    Periodic,
+    /// Any other message.
+    Other(String),
+}
+
+impl NoopKind {
+    /// Classify the message of a no-op.
+    pub(crate) fn new(message: &str) -> NoopKind {
+        match message {
+            "initiating set" => NoopKind::InitiatingSet,
+            "new primary" => NoopKind::NewPrimary,
+            "Reconfig set" => NoopKind::ReconfigSet,
+            "periodic noop" => NoopKind::Periodic,
+            _ => NoopKind::Other(message.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noop_kind_classifies_initiating_set() {
+        assert_eq!(NoopKind::new("initiating set"), NoopKind::InitiatingSet);
+    }
+
+    #[test]
+    fn noop_kind_keeps_unrecognized_messages() {
+        assert_eq!(
+            NoopKind::new("Resuming after rollback"),
+            NoopKind::Other("Resuming after rollback".into())
+        );
+    }
+}
@@ -6,88 +6,219 @@
 //! As we accept _any_ document, it may not be a valid operation so wrap any conversions in a
 //! `Result`.
 
+use std::convert::TryFrom;
 use std::fmt;
 
-use crate::{Error, Result};
-use base64::encode;
-use bson::{Bson, Document};
+use crate::apply;
+use crate::namespace;
+use crate::redact;
+use crate::{
+    CdcRecord, ChangeEvent, CommandKind, Error, Metadata, NoopKind, Result, ServerVersion,
+    UpdateModification,
+};
+use base64::{decode, encode};
+use bson::document::ValueAccessError;
+use bson::{doc, Bson, Document};
 use chrono::{DateTime, TimeZone, Utc};
 use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// A MongoDB oplog operation.
+///
+/// With the `serde` feature enabled, operations can be serialized and deserialized with each
+/// operation keyed by the name of its variant (e.g. `{"Insert": {...}}`).
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(clippy::large_enum_variant)]
 pub enum Operation {
     /// A no-op as inserted periodically by MongoDB or used to initiate new replica sets.
     Noop {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The message associated with this operation.
         message: Option<String>,
     },
     /// An insert of a document into a specific database and collection.
     Insert {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
+        /// The UUID of the collection of the operation, if recorded (MongoDB 3.6 and later).
+        ///
+        /// Unlike the namespace, this is stable across collection renames.
+        collection_uuid: Option<bson::Uuid>,
         /// The BSON document inserted into the namespace.
         document: Document,
+        /// The selection criteria recorded alongside the insert (`o2`), if any, e.g. the query of
+        /// an upsert which inserted the document.
+        query: Option<Document>,
     },
     /// An update of a document in a specific database and collection matching a given query.
     Update {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
+        /// The UUID of the collection of the operation, if recorded (MongoDB 3.6 and later).
+        ///
+        /// Unlike the namespace, this is stable across collection renames.
+        collection_uuid: Option<bson::Uuid>,
         /// The BSON selection criteria for the update.
         query: Document,
         /// The BSON update applied in this operation.
         update: Document,
+        /// The structured interpretation of the update applied in this operation.
+        modification: UpdateModification,
+        /// The full document before the update, if inlined in the oplog entry (`preImage`) or
+        /// resolved by `Operation::resolve_pre_image`.
+        pre_image: Option<Document>,
     },
     /// The deletion of a document in a specific database and collection matching a given query.
     Delete {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
+        /// The UUID of the collection of the operation, if recorded (MongoDB 3.6 and later).
+        ///
+        /// Unlike the namespace, this is stable across collection renames.
+        collection_uuid: Option<bson::Uuid>,
         /// The BSON selection criteria for the delete.
         query: Document,
+        /// The full document before the delete, if inlined in the oplog entry (`preImage`) or
+        /// resolved by `Operation::resolve_pre_image`.
+        pre_image: Option<Document>,
     },
     /// A command such as the creation or deletion of a collection.
     Command {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
+        /// The UUID of the collection of the operation, if recorded (MongoDB 3.6 and later).
+        ///
+        /// Unlike the namespace, this is stable across collection renames.
+        collection_uuid: Option<bson::Uuid>,
         /// The BSON command.
         command: Document,
     },
     /// A command to apply multiple oplog operations at once.
     ApplyOps {
-        /// A unique identifier for this operation.
-        uid: String,
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
         /// The time of the operation.
         timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
         /// A vector of operations to apply.
         operations: Vec<Operation>,
+        /// Whether this is one of several entries of a transaction with more to follow.
+        partial_txn: bool,
+        /// Whether this entry prepares a transaction to be committed by a later
+        /// `commitTransaction` command.
+        prepare: bool,
+    },
+    /// The commit of a prepared transaction.
+    CommitTransaction {
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
+        /// The time of the operation.
+        timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
+        /// The full namespace of the operation including its database and collection.
+        namespace: String,
+        /// The timestamp at which the transaction was committed, if recorded.
+        commit_timestamp: Option<bson::Timestamp>,
+        /// The BSON command.
+        command: Document,
+    },
+    /// The abort of a prepared transaction.
+    AbortTransaction {
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
+        /// The time of the operation.
+        timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
+        /// The full namespace of the operation including its database and collection.
+        namespace: String,
+        /// The BSON command.
+        command: Document,
+    },
+    /// An operation with an unrecognized op code, only returned when parsing with
+    /// `ParseOptions::tolerate_unknown`.
+    Unknown {
+        /// A unique identifier for the session of this operation, if any.
+        uid: Option<String>,
+        /// The time of the operation.
+        timestamp: DateTime<Utc>,
+        /// The raw oplog timestamp of the operation, preserving the order of operations within the
+        /// same second.
+        optime: bson::Timestamp,
+        /// Optional metadata such as the transaction of the operation.
+        metadata: Metadata,
+        /// The unrecognized op code of the operation.
+        op: String,
+        /// The full oplog document of the operation.
+        document: Document,
     },
 }
 
 impl Operation {
     /// Try to create a new Operation from a BSON document.
     ///
+    /// This is also available as `TryFrom<&Document>` and `TryFrom<Document>` so documents can be
+    /// converted with `try_into`.
+    ///
     /// # Example
     ///
     /// ```
@@ -113,435 +244,3874 @@ impl Operation {
     /// # }
     /// ```
     pub fn new(document: &Document) -> Result<Operation> {
+        Operation::with_options(document, &ParseOptions::default())
+    }
+
+    /// Parse a batch of BSON documents, separating the operations from any errors.
+    ///
+    /// Unlike collecting the results of `new`, a malformed document does not discard the rest of
+    /// the batch. Errors are returned with the index of the document which caused them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::doc;
+    /// use oplog::Operation;
+    ///
+    /// let (operations, errors) = Operation::parse_batch(&[doc! { "op": "x" }]);
+    ///
+    /// assert!(operations.is_empty());
+    /// assert_eq!(errors[0].0, 0);
+    /// ```
+    pub fn parse_batch(documents: &[Document]) -> (Vec<Operation>, Vec<(usize, Error)>) {
+        let mut operations = Vec::with_capacity(documents.len());
+        let mut errors = Vec::new();
+
+        for (index, document) in documents.iter().enumerate() {
+            match Operation::new(document) {
+                Ok(operation) => operations.push(operation),
+                Err(err) => errors.push((index, err)),
+            }
+        }
+
+        (operations, errors)
+    }
+
+    /// Try to create a new Operation from the raw bytes of a BSON document (e.g. as read from a
+    /// `mongodump` file).
+    ///
+    /// Returns `Error::Deserialize` if the bytes are not a valid BSON document.
+    pub fn from_slice(bytes: &[u8]) -> Result<Operation> {
+        let document = Document::from_reader(bytes)?;
+
+        Operation::new(&document)
+    }
+
+    /// Try to create a new Operation from a BSON document with the given parsing options.
+    pub fn with_options(document: &Document, options: &ParseOptions) -> Result<Operation> {
+        Operation::parse(document, options, 0)
+    }
+
+    /// Returns an operation for a given document nested `depth` applyOps commands deep.
+    fn parse(document: &Document, options: &ParseOptions, depth: usize) -> Result<Operation> {
+        if depth > options.max_depth {
+            return Err(Error::MaxDepthExceeded);
+        }
+
         let op = document.get_str("op")?;
 
         match op {
             "n" => Operation::from_noop(document),
             "i" => Operation::from_insert(document),
-            "u" => Operation::from_update(document),
+            "u" => Operation::from_update(document, options),
             "d" => Operation::from_delete(document),
-            "c" => Operation::from_command(document),
+            "c" => Operation::from_command(document, options, depth),
+            op if options.tolerate_unknown => Operation::from_unknown(op, document),
             op => Err(Error::UnknownOperation(op.into())),
         }
     }
 
-    /// Returns an operation from any BSON value.
-    fn from_bson(bson: &Bson) -> Result<Operation> {
-        match *bson {
-            Bson::Document(ref document) => Operation::new(document),
-            _ => Err(Error::InvalidOperation),
+    /// Returns the time of the operation.
+    ///
+    /// For `ApplyOps`, this is the time of the outer command rather than that of any of the
+    /// operations it contains.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match *self {
+            Operation::Noop { timestamp, .. }
+            | Operation::Insert { timestamp, .. }
+            | Operation::Update { timestamp, .. }
+            | Operation::Delete { timestamp, .. }
+            | Operation::Command { timestamp, .. }
+            | Operation::ApplyOps { timestamp, .. }
+            | Operation::CommitTransaction { timestamp, .. }
+            | Operation::AbortTransaction { timestamp, .. }
+            | Operation::Unknown { timestamp, .. } => timestamp,
         }
     }
 
-    /// Returns a no-op operation for a given document.
-    fn from_noop(document: &Document) -> Result<Operation> {
-        let ts = document.get_timestamp("ts")?;
-        // We don't always get a document in "o"
-        let message = document
-            .get("o")
-            .and_then(|d| d.as_document())
-            .and_then(|d| d.get("msg"))
-            .and_then(|d| d.as_str())
-            .map(|s| s.to_string());
+    /// Returns the raw oplog timestamp of the operation.
+    ///
+    /// Unlike `timestamp`, this preserves the increment distinguishing operations within the same
+    /// second so it can be persisted and later passed to `OplogBuilder::start_at` to resume from
+    /// exactly this operation.
+    pub fn oplog_ts(&self) -> bson::Timestamp {
+        match *self {
+            Operation::Noop { optime, .. }
+            | Operation::Insert { optime, .. }
+            | Operation::Update { optime, .. }
+            | Operation::Delete { optime, .. }
+            | Operation::Command { optime, .. }
+            | Operation::ApplyOps { optime, .. }
+            | Operation::CommitTransaction { optime, .. }
+            | Operation::AbortTransaction { optime, .. }
+            | Operation::Unknown { optime, .. } => optime,
+        }
+    }
 
-        Ok(Operation::Noop {
-            uid: get_uid(document).unwrap().to_string(),
-            timestamp: timestamp_to_datetime(ts),
-            message,
-        })
+    /// Returns a key ordering operations by their position in the oplog.
+    ///
+    /// This is the time and increment of the raw oplog timestamp so, unlike `timestamp`, it
+    /// distinguishes operations within the same second, e.g. to merge-sort the oplogs of several
+    /// shards with `sort_by_key`. Keys are unique within a single replica set's oplog so equal
+    /// keys are only possible for operations from different shards.
+    ///
+    /// `Operation` does not implement `Ord` itself as operations at the same position need not be
+    /// equal.
+    pub fn sort_key(&self) -> (u32, u32) {
+        let ts = self.oplog_ts();
+
+        (ts.time, ts.increment)
     }
 
-    /// Return an insert operation for a given document.
-    fn from_insert(document: &Document) -> Result<Operation> {
-        let ts = document.get_timestamp("ts")?;
-        let ns = document.get_str("ns")?;
-        let o = document.get_document("o")?;
+    /// Returns the unique identifier for the session of this operation, if any.
+    pub fn uid(&self) -> Option<&str> {
+        match *self {
+            Operation::Noop { ref uid, .. }
+            | Operation::Insert { ref uid, .. }
+            | Operation::Update { ref uid, .. }
+            | Operation::Delete { ref uid, .. }
+            | Operation::Command { ref uid, .. }
+            | Operation::ApplyOps { ref uid, .. }
+            | Operation::CommitTransaction { ref uid, .. }
+            | Operation::AbortTransaction { ref uid, .. }
+            | Operation::Unknown { ref uid, .. } => uid.as_deref(),
+        }
+    }
 
-        Ok(Operation::Insert {
-            uid: get_uid(document).unwrap().to_string(),
-            timestamp: timestamp_to_datetime(ts),
-            namespace: ns.into(),
-            document: o.to_owned(),
-        })
+    /// Returns the optional metadata of the operation such as its transaction number.
+    pub fn metadata(&self) -> &Metadata {
+        match *self {
+            Operation::Noop { ref metadata, .. }
+            | Operation::Insert { ref metadata, .. }
+            | Operation::Update { ref metadata, .. }
+            | Operation::Delete { ref metadata, .. }
+            | Operation::Command { ref metadata, .. }
+            | Operation::ApplyOps { ref metadata, .. }
+            | Operation::CommitTransaction { ref metadata, .. }
+            | Operation::AbortTransaction { ref metadata, .. }
+            | Operation::Unknown { ref metadata, .. } => metadata,
+        }
     }
 
-    /// Return an update operation for a given document.
-    fn from_update(document: &Document) -> Result<Operation> {
-        let ts = document.get_timestamp("ts")?;
-        let ns = document.get_str("ns")?;
-        let o = document.get_document("o")?;
-        let o2 = document.get_document("o2")?;
+    /// Returns the 64-bit hash (`h`) of the operation, if recorded.
+    ///
+    /// Together with the `oplog_ts`, this identifies an entry across a resume boundary (e.g. to
+    /// discard operations already seen) but is absent from the oplogs of MongoDB 4.2 and later.
+    /// See `Metadata::hash`.
+    pub fn hash64(&self) -> Option<i64> {
+        self.metadata().hash
+    }
 
-        Ok(Operation::Update {
-            uid: get_uid(document).unwrap().to_string(),
-            timestamp: timestamp_to_datetime(ts),
-            namespace: ns.into(),
-            query: o2.to_owned(),
-            update: o.to_owned(),
-        })
+    /// Returns whether this is a no-op.
+    pub fn is_noop(&self) -> bool {
+        matches!(*self, Operation::Noop { .. })
     }
 
-    /// Return a delete operation for a given document.
-    fn from_delete(document: &Document) -> Result<Operation> {
-        let ts = document.get_timestamp("ts")?;
-        let ns = document.get_str("ns")?;
-        let o = document.get_document("o")?;
+    /// Returns whether this is an insert.
+    pub fn is_insert(&self) -> bool {
+        matches!(*self, Operation::Insert { .. })
+    }
 
-        Ok(Operation::Delete {
-            uid: get_uid(document).unwrap().to_string(),
-            timestamp: timestamp_to_datetime(ts),
-            namespace: ns.into(),
-            query: o.to_owned(),
-        })
+    /// Returns whether this is an update.
+    pub fn is_update(&self) -> bool {
+        matches!(*self, Operation::Update { .. })
     }
 
-    /// Return a command operation for a given document.
+    /// Returns whether this is an update replacing the whole document rather than modifying it
+    /// with update operators (e.g. `$set`) or a `$v: 2` diff.
     ///
-    /// Note that this can return either an `Operation::Command` or an `Operation::ApplyOps` when
-    /// successful.
-    fn from_command(document: &Document) -> Result<Operation> {
-        let ts = document.get_timestamp("ts")?;
-        let ns = document.get_str("ns")?;
-        let o = document.get_document("o")?;
-
-        match o.get_array("applyOps") {
-            Ok(ops) => {
-                let operations = ops
-                    .iter()
-                    .map(|bson| Operation::from_bson(bson))
-                    .collect::<Result<Vec<Operation>>>()?;
-
-                Ok(Operation::ApplyOps {
-                    uid: get_uid(document).unwrap().to_string(),
-                    timestamp: timestamp_to_datetime(ts),
-                    namespace: ns.into(),
-                    operations: operations,
-                })
+    /// See `UpdateModification` for the distinction.
+    pub fn is_replacement(&self) -> bool {
+        matches!(
+            *self,
+            Operation::Update {
+                modification: UpdateModification::Replacement(_),
+                ..
             }
-            Err(_) => Ok(Operation::Command {
-                uid: get_uid(document).unwrap().to_string(),
-                timestamp: timestamp_to_datetime(ts),
-                namespace: ns.into(),
-                command: o.to_owned(),
-            }),
+        )
+    }
+
+    /// Returns whether this is a delete.
+    pub fn is_delete(&self) -> bool {
+        matches!(*self, Operation::Delete { .. })
+    }
+
+    /// Returns whether this is a command other than `ApplyOps` or a transaction command.
+    pub fn is_command(&self) -> bool {
+        matches!(*self, Operation::Command { .. })
+    }
+
+    /// Returns whether this is an `ApplyOps`.
+    pub fn is_apply_ops(&self) -> bool {
+        matches!(*self, Operation::ApplyOps { .. })
+    }
+
+    /// Returns the kind of the operation, i.e. its variant without any of its fields.
+    ///
+    /// Unlike the operation itself, this is `Copy` and `Hash` so is suited to keying counters or
+    /// routing tables by kind.
+    pub fn kind(&self) -> OperationKind {
+        match *self {
+            Operation::Noop { .. } => OperationKind::Noop,
+            Operation::Insert { .. } => OperationKind::Insert,
+            Operation::Update { .. } => OperationKind::Update,
+            Operation::Delete { .. } => OperationKind::Delete,
+            Operation::Command { .. } => OperationKind::Command,
+            Operation::ApplyOps { .. } => OperationKind::ApplyOps,
+            Operation::CommitTransaction { .. } => OperationKind::CommitTransaction,
+            Operation::AbortTransaction { .. } => OperationKind::AbortTransaction,
+            Operation::Unknown { .. } => OperationKind::Unknown,
         }
     }
-}
 
-impl fmt::Display for Operation {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    /// Returns the MongoDB oplog op code of the operation.
+    ///
+    /// This is `"n"` for no-ops, `"i"` for inserts, `"u"` for updates, `"d"` for deletes and
+    /// `"c"` for commands, including `ApplyOps` and transaction commands as they originate from
+    /// commands. `Unknown` operations return their unrecognized op code.
+    pub fn op_char(&self) -> &str {
         match *self {
-            Operation::Noop {
-                ref uid,
-                timestamp,
-                ref message,
-            } => {
-                write!(f, "No-op #{} at {}: {:?}", uid, timestamp, message)
+            Operation::Noop { .. } => "n",
+            Operation::Insert { .. } => "i",
+            Operation::Update { .. } => "u",
+            Operation::Delete { .. } => "d",
+            Operation::Command { .. }
+            | Operation::ApplyOps { .. }
+            | Operation::CommitTransaction { .. }
+            | Operation::AbortTransaction { .. } => "c",
+            Operation::Unknown { ref op, .. } => op,
+        }
+    }
+
+    /// Returns the full namespace of the operation including its database and collection.
+    ///
+    /// No-ops are not associated with any namespace so this returns `None` for them. `Unknown`
+    /// operations return the `ns` of their document, if any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "i",
+    ///     "ns": "foo.bar",
+    ///     "o": {
+    ///         "foo": "bar"
+    ///     }
+    /// };
+    /// let operation = Operation::new(&document)?;
+    ///
+    /// assert_eq!(operation.namespace(), Some("foo.bar"));
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn namespace(&self) -> Option<&str> {
+        match *self {
+            Operation::Noop { .. } => None,
+            Operation::Insert { ref namespace, .. }
+            | Operation::Update { ref namespace, .. }
+            | Operation::Delete { ref namespace, .. }
+            | Operation::Command { ref namespace, .. }
+            | Operation::ApplyOps { ref namespace, .. }
+            | Operation::CommitTransaction { ref namespace, .. }
+            | Operation::AbortTransaction { ref namespace, .. } => Some(namespace),
+            Operation::Unknown { ref document, .. } => document.get_str("ns").ok(),
+        }
+    }
+
+    /// Returns the database of the operation's namespace.
+    ///
+    /// This is everything before the first `.` in the namespace, e.g. `foo` in `foo.bar.baz`.
+    pub fn database(&self) -> Option<&str> {
+        self.namespace()
+            .map(|ns| ns.split_once('.').map_or(ns, |(database, _)| database))
+    }
+
+    /// Returns the collection of the operation's namespace.
+    ///
+    /// This is everything after the first `.` in the namespace so collection names containing dots
+    /// are preserved, e.g. `bar.baz` in `foo.bar.baz` and `$cmd` in `foo.$cmd`.
+    pub fn collection(&self) -> Option<&str> {
+        self.namespace()
+            .and_then(|ns| ns.split_once('.'))
+            .map(|(_, collection)| collection)
+    }
+
+    /// Returns whether the operation's namespace matches a glob pattern, e.g. `sales.orders_*`.
+    ///
+    /// A `*` matches any run of characters within the database or collection of the namespace, as
+    /// with the patterns of `NamespaceFilter`. No-ops have no namespace so never match.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "i",
+    ///     "ns": "sales.orders_2024",
+    ///     "o": { "_id": 1 }
+    /// };
+    /// let operation = Operation::new(&document)?;
+    ///
+    /// assert!(operation.namespace_matches("sales.orders_*"));
+    /// assert!(!operation.namespace_matches("*.customers"));
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn namespace_matches(&self, pattern: &str) -> bool {
+        self.namespace()
+            .is_some_and(|namespace| namespace::glob_matches(pattern, namespace))
+    }
+
+    /// Returns the UUID of the collection of the operation, if recorded.
+    ///
+    /// See the `collection_uuid` field of the data-bearing variants.
+    pub fn collection_uuid(&self) -> Option<bson::Uuid> {
+        match *self {
+            Operation::Insert {
+                collection_uuid, ..
+            }
+            | Operation::Update {
+                collection_uuid, ..
+            }
+            | Operation::Delete {
+                collection_uuid, ..
             }
+            | Operation::Command {
+                collection_uuid, ..
+            } => collection_uuid,
+            _ => None,
+        }
+    }
+
+    /// Returns every document carried by the operation, e.g. to index operation payloads for
+    /// search without matching on each variant.
+    ///
+    /// These are the document of an `Insert` (followed by any upsert query), the query and update
+    /// of an `Update`, the query of a `Delete` (followed by any pre-image of either), the body of
+    /// a command and the full oplog document of an `Unknown` operation. The documents of the
+    /// operations contained in `ApplyOps` are returned in order. A `Noop` has no documents.
+    pub fn documents(&self) -> Vec<&Document> {
+        match *self {
+            Operation::Noop { .. } => Vec::new(),
             Operation::Insert {
-                ref uid,
-                timestamp,
-                ref namespace,
                 ref document,
+                ref query,
+                ..
             } => {
-                write!(
-                    f,
-                    "Insert #{} into {} at {}: {}",
-                    uid, namespace, timestamp, document
-                )
+                let mut documents = vec![document];
+                documents.extend(query);
+
+                documents
             }
+            Operation::Unknown { ref document, .. } => vec![document],
             Operation::Update {
-                ref uid,
-                timestamp,
-                ref namespace,
                 ref query,
                 ref update,
+                ref pre_image,
+                ..
             } => {
-                write!(
-                    f,
-                    "Update #{} {} with {} at {}: {}",
-                    uid, namespace, query, timestamp, update
-                )
+                let mut documents = vec![query, update];
+                documents.extend(pre_image);
+
+                documents
             }
             Operation::Delete {
-                ref uid,
-                timestamp,
-                ref namespace,
                 ref query,
+                ref pre_image,
+                ..
             } => {
-                write!(
-                    f,
-                    "Delete #{} from {} at {}: {}",
-                    uid, namespace, timestamp, query
-                )
-            }
-            Operation::Command {
-                ref uid,
-                timestamp,
-                ref namespace,
-                ref command,
-            } => {
-                write!(
-                    f,
-                    "Command #{} {} at {}: {}",
-                    uid, namespace, timestamp, command
-                )
+                let mut documents = vec![query];
+                documents.extend(pre_image);
+
+                documents
             }
-            Operation::ApplyOps {
-                ref uid,
-                timestamp,
-                ref namespace,
-                ref operations,
-            } => {
-                write!(
-                    f,
-                    "ApplyOps #{} {} at {}: {} operations",
-                    uid,
-                    namespace,
-                    timestamp,
-                    operations.len()
-                )
+            Operation::Command { ref command, .. }
+            | Operation::CommitTransaction { ref command, .. }
+            | Operation::AbortTransaction { ref command, .. } => vec![command],
+            Operation::ApplyOps { ref operations, .. } => {
+                operations.iter().flat_map(Operation::documents).collect()
             }
         }
     }
-}
-
-/// Convert a BSON timestamp into a UTC `DateTime`.
-fn timestamp_to_datetime(timestamp: bson::Timestamp) -> DateTime<Utc> {
-    let seconds = timestamp.time;
-    let nanoseconds = timestamp.increment;
-
-    Utc.timestamp(seconds as i64, nanoseconds)
-}
-
-fn get_uid(document: &Document) -> Result<String> {
-    let lsid = document.get_document("lsid")?;
-    let bytes = lsid.get_binary_generic("uid")?;
 
-    let uid = encode(bytes);
-
-    Ok(uid)
-}
+    /// Returns whether two operations have the same content, ignoring when and how they were
+    /// written, e.g. to compare operations against golden files in tests.
+    ///
+    /// Operations are compared by their kind, namespace and documents (see `documents`) so their
+    /// `uid`, `timestamp`, `optime` and `metadata` (including the `hash`) are ignored, as is the
+    /// message of a `Noop`. The operations contained in `ApplyOps` are compared in turn while
+    /// `Unknown` operations are compared by their full oplog document.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let insert = |time| {
+    ///     Operation::new(&doc! {
+    ///         "ts": Bson::Timestamp(bson::Timestamp { time, increment: 0 }),
+    ///         "op": "i",
+    ///         "ns": "foo.bar",
+    ///         "o": { "_id": 1 }
+    ///     })
+    /// };
+    ///
+    /// assert!(insert(1479561394)?.content_eq(&insert(1479561395)?));
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn content_eq(&self, other: &Operation) -> bool {
+        match (self, other) {
+            (
+                Operation::ApplyOps {
+                    operations,
+                    namespace,
+                    ..
+                },
+                Operation::ApplyOps {
+                    operations: other_operations,
+                    namespace: other_namespace,
+                    ..
+                },
+            ) => {
+                namespace == other_namespace
+                    && operations.len() == other_operations.len()
+                    && operations
+                        .iter()
+                        .zip(other_operations)
+                        .all(|(operation, other)| operation.content_eq(other))
+            }
+            _ => {
+                self.kind() == other.kind()
+                    && self.namespace() == other.namespace()
+                    && self.documents() == other.documents()
+            }
+        }
+    }
+
+    /// Apply a transformation in place to every document carried by the operation, e.g. to rename
+    /// a field before forwarding operations elsewhere.
+    ///
+    /// The documents are those returned by `documents`, including those of the operations
+    /// contained in `ApplyOps`. The `modification` of an `Update` is reinterpreted from its
+    /// transformed update unless the transformation leaves it invalid.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "i",
+    ///     "ns": "foo.bar",
+    ///     "o": { "_id": 1, "legacy_name": "foo" }
+    /// };
+    /// let mut operation = Operation::new(&document)?;
+    ///
+    /// operation.map_documents(|document| {
+    ///     if let Some(value) = document.remove("legacy_name") {
+    ///         document.insert("name", value);
+    ///     }
+    /// });
+    ///
+    /// if let Operation::Insert { document, .. } = operation {
+    ///     assert_eq!(document, doc! { "_id": 1, "name": "foo" });
+    /// }
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn map_documents<F: FnMut(&mut Document)>(&mut self, mut f: F) {
+        self.map_documents_with(&mut f);
+    }
+
+    /// Apply a transformation in place to every document carried by the operation.
+    fn map_documents_with(&mut self, f: &mut dyn FnMut(&mut Document)) {
+        match *self {
+            Operation::Noop { .. } => {}
+            Operation::Insert {
+                ref mut document,
+                ref mut query,
+                ..
+            } => {
+                f(document);
+                if let Some(ref mut query) = *query {
+                    f(query);
+                }
+            }
+            Operation::Unknown {
+                ref mut document, ..
+            } => f(document),
+            Operation::Update {
+                ref mut query,
+                ref mut update,
+                ref mut modification,
+                ref mut pre_image,
+                ..
+            } => {
+                f(query);
+                f(update);
+                if let Ok(reinterpreted) = UpdateModification::new(update) {
+                    *modification = reinterpreted;
+                }
+                if let Some(ref mut pre_image) = *pre_image {
+                    f(pre_image);
+                }
+            }
+            Operation::Delete {
+                ref mut query,
+                ref mut pre_image,
+                ..
+            } => {
+                f(query);
+                if let Some(ref mut pre_image) = *pre_image {
+                    f(pre_image);
+                }
+            }
+            Operation::Command {
+                ref mut command, ..
+            }
+            | Operation::CommitTransaction {
+                ref mut command, ..
+            }
+            | Operation::AbortTransaction {
+                ref mut command, ..
+            } => f(command),
+            Operation::ApplyOps {
+                ref mut operations, ..
+            } => {
+                for operation in operations {
+                    operation.map_documents_with(f);
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the operation with the given fields of its documents replaced by
+    /// `"***"`, e.g. to log operations without leaking personal data.
+    ///
+    /// Fields are given as dotted paths (e.g. `card.number`) relative to the documents of the
+    /// operation as returned by `documents`, so they are also redacted from the fields changed by
+    /// update operators and diffs. The operations contained in `ApplyOps` are redacted too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "i",
+    ///     "ns": "foo.bar",
+    ///     "o": { "_id": 1, "user": { "email": "a@example.com" } }
+    /// };
+    /// let operation = Operation::new(&document)?.redact(&["user.email"]);
+    ///
+    /// if let Operation::Insert { document, .. } = operation {
+    ///     assert_eq!(document, doc! { "_id": 1, "user": { "email": "***" } });
+    /// }
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn redact(&self, fields: &[&str]) -> Operation {
+        redact::redact(self, fields)
+    }
+
+    /// Returns an estimate of the size of the operation in bytes.
+    ///
+    /// This is the serialized BSON length of the operation's documents plus the lengths of its
+    /// namespace and uid, summed over the operations contained in `ApplyOps`. It is intended for
+    /// capping batches (e.g. at MongoDB's 16MB document limit) rather than being exact.
+    pub fn size_bytes(&self) -> usize {
+        let documents = match *self {
+            Operation::Noop { ref message, .. } => message.as_ref().map_or(0, String::len),
+            Operation::Insert {
+                ref document,
+                ref query,
+                ..
+            } => bson_len(document) + query.as_ref().map_or(0, bson_len),
+            Operation::Update {
+                ref query,
+                ref update,
+                ref pre_image,
+                ..
+            } => bson_len(query) + bson_len(update) + pre_image.as_ref().map_or(0, bson_len),
+            Operation::Delete {
+                ref query,
+                ref pre_image,
+                ..
+            } => bson_len(query) + pre_image.as_ref().map_or(0, bson_len),
+            Operation::Command { ref command, .. }
+            | Operation::CommitTransaction { ref command, .. }
+            | Operation::AbortTransaction { ref command, .. } => bson_len(command),
+            Operation::ApplyOps { ref operations, .. } => {
+                operations.iter().map(Operation::size_bytes).sum()
+            }
+            Operation::Unknown { ref document, .. } => bson_len(document),
+        };
+
+        documents + self.namespace().map_or(0, str::len) + self.uid().map_or(0, str::len)
+    }
+
+    /// Returns the kind of command of an `Operation::Command`.
+    ///
+    /// This classifies the command document (e.g. as an index build) while the full document
+    /// remains available in `command`. Any other operation returns `None`.
+    pub fn command_kind(&self) -> Option<CommandKind> {
+        match *self {
+            Operation::Command {
+                ref namespace,
+                ref command,
+                ..
+            } => Some(CommandKind::new(namespace, command)),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of no-op of an `Operation::Noop` with a message.
+    ///
+    /// This classifies messages written by replica set state changes (e.g. `"new primary"`) while
+    /// the raw message remains available in `message`. No-ops without a message and any other
+    /// operation return `None`.
+    pub fn noop_kind(&self) -> Option<NoopKind> {
+        match *self {
+            Operation::Noop {
+                message: Some(ref message),
+                ..
+            } => Some(NoopKind::new(message)),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the operation changes the schema rather than the data of a deployment.
+    ///
+    /// This is true for commands creating, dropping, renaming or modifying collections and
+    /// indexes (see `command_kind`) and dropping databases, e.g. so a replication sink can drain
+    /// pending writes before applying them. Inserts, updates, deletes, no-ops and other commands
+    /// (including `emptycapped`, which only removes documents, `ApplyOps` and transaction
+    /// commands) are not DDL.
+    pub fn is_ddl(&self) -> bool {
+        match self.command_kind() {
+            Some(CommandKind::EmptyCapped { .. }) | Some(CommandKind::Other(_)) => false,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the operation as an event shaped like that of a MongoDB change stream.
+    ///
+    /// Inserts, updates, deletes and recognized collection and index commands (see
+    /// `command_kind`) have an event while no-ops and other operations return `None`. Updates
+    /// with update operators or a diff describe their changed fields in `update_description`
+    /// while replacements become `replace` events with a `full_document`. Use `flatten` to convert
+    /// the operations contained in an `ApplyOps`.
+    pub fn to_change_event(&self) -> Option<ChangeEvent> {
+        ChangeEvent::new(self)
+    }
+
+    /// Returns the operation as a normalized change record for a change data capture sink.
+    ///
+    /// Inserts, updates and deletes have a record while no-ops and commands return `None`. As the
+    /// oplog does not record the full document before or after most changes, see `CdcRecord` for
+    /// exactly what is populated. Use `flatten` to convert the operations contained in an
+    /// `ApplyOps`.
+    pub fn to_cdc_record(&self) -> Option<CdcRecord> {
+        CdcRecord::new(self)
+    }
+
+    /// Returns the operation as a key and value for a log-compacted sink such as a compacted Kafka
+    /// topic, where a null value (a tombstone) marks the deletion of its key.
+    ///
+    /// The key is the `_id` of the affected document (see `object_id`). Deletes have no value
+    /// while inserts and updates have the document after the change as populated by
+    /// `CdcRecord::after`, which for an update with operators or a diff holds only the fields it
+    /// set. No-ops and commands return `None`; use `flatten` to convert the operations contained
+    /// in an `ApplyOps`.
+    pub fn to_tombstone(&self) -> Option<(Bson, Option<Document>)> {
+        let id = self.object_id()?.clone();
+
+        match *self {
+            Operation::Delete { .. } => Some((id, None)),
+            _ => Some((id, self.to_cdc_record()?.after)),
+        }
+    }
+
+    /// Check that the operation is semantically sound, e.g. before applying it to another
+    /// deployment, returning `Error::Validation` describing the first problem found.
+    ///
+    /// Parsing only checks the structure of oplog entries whereas this checks that:
+    ///
+    /// * namespaces are well-formed, i.e. a database without `/\. "$` followed by `.` and a
+    ///   collection, which is `$cmd` for commands;
+    /// * inserts and deletes identify their document by `_id`;
+    /// * updates have a non-empty query (`o2`).
+    ///
+    /// The operations contained in `ApplyOps` are validated in turn and `Unknown` operations
+    /// return `Error::UnknownOperation`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "d",
+    ///     "ns": "foo.bar",
+    ///     "o": { "name": "foo" }
+    /// };
+    /// let operation = Operation::new(&document)?;
+    ///
+    /// assert!(operation.validate().is_err());
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Operation::Noop { .. }
+            | Operation::CommitTransaction { .. }
+            | Operation::AbortTransaction { .. } => Ok(()),
+            Operation::Insert {
+                ref namespace,
+                ref document,
+                ..
+            } => {
+                validate_namespace(namespace, false)?;
+                validate_id(document, "insert document")
+            }
+            Operation::Update {
+                ref namespace,
+                ref query,
+                ..
+            } => {
+                validate_namespace(namespace, false)?;
+                if query.is_empty() {
+                    return Err(Error::Validation("update has an empty query (o2)".into()));
+                }
+
+                Ok(())
+            }
+            Operation::Delete {
+                ref namespace,
+                ref query,
+                ..
+            } => {
+                validate_namespace(namespace, false)?;
+                validate_id(query, "delete query")
+            }
+            Operation::Command { ref namespace, .. } => validate_namespace(namespace, true),
+            Operation::ApplyOps {
+                ref namespace,
+                ref operations,
+                ..
+            } => {
+                validate_namespace(namespace, true)?;
+                operations.iter().try_for_each(Operation::validate)
+            }
+            Operation::Unknown { ref op, .. } => Err(Error::UnknownOperation(op.clone())),
+        }
+    }
+
+    /// Apply the operation to the given database, e.g. to replicate it to another deployment.
+    ///
+    /// Inserts and replacements are upserted by `_id` and updates and deletes use their original
+    /// query so that replaying an operation is idempotent. Diff updates are applied as the
+    /// equivalent `$set` and `$unset` and commands are run against the database as recorded,
+    /// except that two-phase index builds create their indexes when committed. The operations of
+    /// an `ApplyOps` are applied in order. No-ops and transaction commands are ignored.
+    ///
+    /// Operations are applied to the collection of the same name in the given database rather
+    /// than in their original database.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    /// use mongodb::Client;
+    /// use oplog::Oplog;
+    ///
+    /// # async fn run() -> Result<(), oplog::Error> {
+    /// let source = Client::with_uri_str("mongodb://source").await?;
+    /// let target = Client::with_uri_str("mongodb://target").await?;
+    /// let db = target.database("replica");
+    ///
+    /// let mut oplog = Oplog::builder()
+    ///     .namespace_filter("sales.*")
+    ///     .build(&source)
+    ///     .await?;
+    ///
+    /// while let Some(res) = oplog.next().await {
+    ///     res?.apply_to(&db).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn apply_to(&self, db: &mongodb::Database) -> Result<()> {
+        apply::apply(self, db, None).await
+    }
+
+    /// Apply the operation to the given database within a session.
+    ///
+    /// See `apply_to`. The operations of an `ApplyOps` are all applied within the same session.
+    pub async fn apply_to_with_session(
+        &self,
+        db: &mongodb::Database,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<()> {
+        apply::apply(self, db, Some(session)).await
+    }
+
+    /// Returns the `_id` of the document affected by an insert, update or delete.
+    ///
+    /// This is taken from the inserted document or the query of an update or delete and may be
+    /// any BSON value, not only an `ObjectId`. Any other operation returns `None`.
+    pub fn object_id(&self) -> Option<&Bson> {
+        match *self {
+            Operation::Insert { ref document, .. } => document.get("_id"),
+            Operation::Update { ref query, .. } | Operation::Delete { ref query, .. } => {
+                query.get("_id")
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the generation time embedded in the `_id` of the affected document, if it is an
+    /// `ObjectId`.
+    ///
+    /// This is usually when the document was first inserted, not when the operation ran, so is
+    /// only a rough estimate of an operation's age where no `wall` clock time is recorded. Other
+    /// `_id` types and operations without an `_id` (see `object_id`) return `None`.
+    pub fn document_id_time(&self) -> Option<DateTime<Utc>> {
+        match *self.object_id()? {
+            Bson::ObjectId(ref id) => Utc
+                .timestamp_millis_opt(id.timestamp().timestamp_millis())
+                .single(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the operation affects the document with the given `_id`, e.g. to replay
+    /// every operation which touched a document.
+    ///
+    /// This compares the given id with `object_id` and, for `ApplyOps`, that of each operation it
+    /// contains. BSON values are compared exactly so an `Int32` id does not match an equal
+    /// `Int64`. Any other operation returns `false`.
+    pub fn affects_id(&self, id: &Bson) -> bool {
+        match *self {
+            Operation::ApplyOps { ref operations, .. } => {
+                operations.iter().any(|operation| operation.affects_id(id))
+            }
+            _ => self.object_id() == Some(id),
+        }
+    }
+
+    /// Returns the key identifying the document affected by an update or delete.
+    ///
+    /// This is the `query` of the operation which, for sharded collections, includes the shard
+    /// key fields as well as `_id` so a delete or update can be routed to the right shard. Any
+    /// other operation returns `None`.
+    pub fn document_key(&self) -> Option<&Document> {
+        match *self {
+            Operation::Update { ref query, .. } | Operation::Delete { ref query, .. } => {
+                Some(query)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the full document before an update or delete, if known.
+    ///
+    /// Only servers with pre-images enabled record them and, rather than inlining them in the
+    /// oplog entry, they usually store them elsewhere so this is `None` until resolved by
+    /// `resolve_pre_image`. Any other operation returns `None`.
+    pub fn pre_image(&self) -> Option<&Document> {
+        match *self {
+            Operation::Update { ref pre_image, .. } | Operation::Delete { ref pre_image, .. } => {
+                pre_image.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve the pre-image of an update or delete with a secondary lookup, returning it if
+    /// found.
+    ///
+    /// Pre-images inlined in the oplog entry are returned without querying the server. Otherwise,
+    /// this reads the oplog entry at the operation's `Metadata::pre_image_op_time` (as written for
+    /// `findAndModify`) or, for collections with `changeStreamPreAndPostImages` enabled, the
+    /// `config.system.preimages` collection. Either may have been truncated or expired by the time
+    /// of the lookup, in which case this returns `None`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    /// use mongodb::Client;
+    /// use oplog::Oplog;
+    ///
+    /// # async fn run() -> Result<(), oplog::Error> {
+    /// let client = Client::with_uri_str("mongodb://localhost").await?;
+    /// let mut oplog = Oplog::new(&client).await?;
+    ///
+    /// while let Some(res) = oplog.next().await {
+    ///     let mut oper = res?;
+    ///     if oper.is_delete() {
+    ///         println!("{:?}", oper.resolve_pre_image(&client).await?);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn resolve_pre_image(
+        &mut self,
+        client: &mongodb::Client,
+    ) -> Result<Option<&Document>> {
+        if self.pre_image().is_none() {
+            let found = self.lookup_pre_image(client).await?;
+
+            match *self {
+                Operation::Update {
+                    ref mut pre_image, ..
+                }
+                | Operation::Delete {
+                    ref mut pre_image, ..
+                } => *pre_image = found,
+                _ => {}
+            }
+        }
+
+        Ok(self.pre_image())
+    }
+
+    /// Look up the pre-image of an update or delete stored outside of its oplog entry.
+    async fn lookup_pre_image(&self, client: &mongodb::Client) -> Result<Option<Document>> {
+        if !self.is_update() && !self.is_delete() {
+            return Ok(None);
+        }
+
+        if let Some(op_time) = self.metadata().pre_image_op_time {
+            let entry = client
+                .database("local")
+                .collection::<Document>("oplog.rs")
+                .find_one(doc! { "ts": op_time }, None)
+                .await?;
+
+            return Ok(entry.and_then(|entry| entry.get_document("o").ok().cloned()));
+        }
+
+        let uuid = match self.collection_uuid() {
+            Some(uuid) => uuid,
+            None => return Ok(None),
+        };
+        let mut filter = doc! { "_id.nsUUID": uuid, "_id.ts": self.oplog_ts() };
+        if let Some(id) = self.object_id() {
+            filter.insert("preImage._id", id.clone());
+        }
+
+        let image = client
+            .database("config")
+            .collection::<Document>("system.preimages")
+            .find_one(filter, None)
+            .await?;
+
+        Ok(image.and_then(|image| image.get_document("preImage").ok().cloned()))
+    }
+
+    /// Returns the fields set by an update and their new values, e.g. to write them as columns.
+    ///
+    /// See `UpdateModification::set_fields`. Replacements and any other operation return `None`.
+    pub fn set_fields(&self) -> Option<Document> {
+        match *self {
+            Operation::Update {
+                ref modification, ..
+            } => modification.set_fields(),
+            _ => None,
+        }
+    }
+
+    /// Returns the paths of the fields removed by an update.
+    ///
+    /// See `UpdateModification::unset_fields`. Replacements and any other operation return
+    /// `None`.
+    pub fn unset_fields(&self) -> Option<Vec<&str>> {
+        match *self {
+            Operation::Update {
+                ref modification, ..
+            } => modification.unset_fields(),
+            _ => None,
+        }
+    }
+
+    /// Returns the leaf operations of this operation in order.
+    ///
+    /// For `ApplyOps`, this recursively expands any nested `applyOps` commands so only the
+    /// operations they contain are returned. Any other operation returns only itself.
+    pub fn flatten(&self) -> Vec<&Operation> {
+        match *self {
+            Operation::ApplyOps { ref operations, .. } => {
+                operations.iter().flat_map(Operation::flatten).collect()
+            }
+            _ => vec![self],
+        }
+    }
+
+    /// Returns the operation as canonical MongoDB Extended JSON (v2).
+    ///
+    /// The resulting object mirrors the shape of the original oplog entry with its `op`, `ns`,
+    /// `ts` and payload (`o` and, for updates, `o2`) so BSON types such as `ObjectId`, `Timestamp`
+    /// and binary data are rendered in their canonical `$oid`, `$timestamp` and `$binary` forms.
+    ///
+    /// This requires the `extended-json` feature.
+    #[cfg(feature = "extended-json")]
+    pub fn to_extended_json(&self) -> serde_json::Value {
+        Bson::Document(self.to_document()).into_canonical_extjson()
+    }
+
+    /// Returns an operation nested in an applyOps command from any BSON value.
+    ///
+    /// Nested operations often omit their own `ts`, `h`, `v`, `wall` and `lsid` (those of a
+    /// transaction carry only `op`, `ns`, `o` and perhaps `o2` and `ui`), in which case they
+    /// inherit those of the `parent` command so they share its timestamp, version and uid. Their
+    /// order within the command is then the only record of their sequence and is preserved.
+    fn from_bson(
+        bson: &Bson,
+        parent: &Document,
+        options: &ParseOptions,
+        depth: usize,
+    ) -> Result<Operation> {
+        let document = match *bson {
+            Bson::Document(ref document) => document,
+            _ => return Err(Error::InvalidOperation),
+        };
+
+        let inherited: Vec<(&str, &Bson)> = ["ts", "h", "v", "wall", "lsid"]
+            .iter()
+            .filter(|key| !document.contains_key(key))
+            .filter_map(|key| parent.get(key).map(|value| (*key, value)))
+            .collect();
+
+        if inherited.is_empty() {
+            return Operation::parse(document, options, depth);
+        }
+
+        let mut document = document.clone();
+        for (key, value) in inherited {
+            document.insert(key, value.clone());
+        }
+
+        Operation::parse(&document, options, depth)
+    }
+
+    /// Returns a no-op operation for a given document.
+    fn from_noop(document: &Document) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+        // We don't always get a document in "o"
+        let message = document
+            .get("o")
+            .and_then(|d| d.as_document())
+            .and_then(|d| d.get("msg"))
+            .and_then(|d| d.as_str())
+            .map(|s| s.to_string());
+
+        Ok(Operation::Noop {
+            uid: get_uid(document)?,
+            timestamp: timestamp_to_datetime(ts),
+            optime: ts,
+            metadata: Metadata::new(document)?,
+            message,
+        })
+    }
+
+    /// Return an insert operation for a given document.
+    fn from_insert(document: &Document) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+        let ns = document.get_str("ns")?;
+        let o = document.get_document("o")?;
+
+        Ok(Operation::Insert {
+            uid: get_uid(document)?,
+            timestamp: timestamp_to_datetime(ts),
+            optime: ts,
+            metadata: Metadata::new(document)?,
+            namespace: ns.into(),
+            collection_uuid: get_collection_uuid(document)?,
+            document: o.to_owned(),
+            query: get_optional_document(document, "o2")?,
+        })
+    }
+
+    /// Return an update operation for a given document.
+    fn from_update(document: &Document, options: &ParseOptions) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+        let ns = document.get_str("ns")?;
+        let o = document.get_document("o")?;
+        let o2 = document.get_document("o2")?;
+
+        Ok(Operation::Update {
+            uid: get_uid(document)?,
+            timestamp: timestamp_to_datetime(ts),
+            optime: ts,
+            metadata: Metadata::new(document)?,
+            namespace: ns.into(),
+            collection_uuid: get_collection_uuid(document)?,
+            query: o2.to_owned(),
+            update: o.to_owned(),
+            modification: UpdateModification::with_server_version(o, options.server_version)?,
+            pre_image: get_optional_document(document, "preImage")?,
+        })
+    }
+
+    /// Return a delete operation for a given document.
+    fn from_delete(document: &Document) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+        let ns = document.get_str("ns")?;
+        let o = document.get_document("o")?;
+
+        Ok(Operation::Delete {
+            uid: get_uid(document)?,
+            timestamp: timestamp_to_datetime(ts),
+            optime: ts,
+            metadata: Metadata::new(document)?,
+            namespace: ns.into(),
+            collection_uuid: get_collection_uuid(document)?,
+            query: o.to_owned(),
+            pre_image: get_optional_document(document, "preImage")?,
+        })
+    }
+
+    /// Return an operation with an unrecognized op code for a given document.
+    fn from_unknown(op: &str, document: &Document) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+
+        Ok(Operation::Unknown {
+            uid: get_uid(document)?,
+            timestamp: timestamp_to_datetime(ts),
+            optime: ts,
+            metadata: Metadata::new(document)?,
+            op: op.into(),
+            document: document.to_owned(),
+        })
+    }
+
+    /// Return a command operation for a given document.
+    ///
+    /// Note that this can return an `Operation::Command`, `Operation::ApplyOps`,
+    /// `Operation::CommitTransaction` or `Operation::AbortTransaction` when successful.
+    fn from_command(
+        document: &Document,
+        options: &ParseOptions,
+        depth: usize,
+    ) -> Result<Operation> {
+        let ts = document.get_timestamp("ts")?;
+        let ns = document.get_str("ns")?;
+        let o = document.get_document("o")?;
+
+        if let Ok(ops) = o.get_array("applyOps") {
+            let operations = ops
+                .iter()
+                .map(|bson| Operation::from_bson(bson, document, options, depth + 1))
+                .collect::<Result<Vec<Operation>>>()?;
+
+            Ok(Operation::ApplyOps {
+                uid: get_uid(document)?,
+                timestamp: timestamp_to_datetime(ts),
+                optime: ts,
+                metadata: Metadata::new(document)?,
+                namespace: ns.into(),
+                operations,
+                partial_txn: o.get_bool("partialTxn").unwrap_or(false),
+                prepare: o.get_bool("prepare").unwrap_or(false),
+            })
+        } else if o.contains_key("commitTransaction") {
+            Ok(Operation::CommitTransaction {
+                uid: get_uid(document)?,
+                timestamp: timestamp_to_datetime(ts),
+                optime: ts,
+                metadata: Metadata::new(document)?,
+                namespace: ns.into(),
+                commit_timestamp: o.get_timestamp("commitTimestamp").ok(),
+                command: o.to_owned(),
+            })
+        } else if o.contains_key("abortTransaction") {
+            Ok(Operation::AbortTransaction {
+                uid: get_uid(document)?,
+                timestamp: timestamp_to_datetime(ts),
+                optime: ts,
+                metadata: Metadata::new(document)?,
+                namespace: ns.into(),
+                command: o.to_owned(),
+            })
+        } else {
+            Ok(Operation::Command {
+                uid: get_uid(document)?,
+                timestamp: timestamp_to_datetime(ts),
+                optime: ts,
+                metadata: Metadata::new(document)?,
+                namespace: ns.into(),
+                collection_uuid: get_collection_uuid(document)?,
+                command: o.to_owned(),
+            })
+        }
+    }
+
+    /// Returns the operation as a document in the shape of the oplog entry it was parsed from.
+    ///
+    /// Parsing the document with `Operation::new` returns an operation equal to this one, so
+    /// operations can be stored or sent on in oplog format. Fields which are not stored by
+    /// `Operation` (e.g. anything in a no-op besides its `msg`, or the `count` of an `applyOps`
+    /// command) are not written back.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::Operation;
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "i",
+    ///     "ns": "foo.bar",
+    ///     "o": { "_id": 1, "foo": "bar" }
+    /// };
+    /// let operation = Operation::new(&document)?;
+    ///
+    /// assert_eq!(operation.to_document(), document);
+    /// assert_eq!(Operation::new(&operation.to_document())?, operation);
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn to_document(&self) -> Document {
+        if let Operation::Unknown { ref document, .. } = *self {
+            return document.clone();
+        }
+
+        let mut document = Document::new();
+
+        let (optime, namespace, o, o2) = match *self {
+            Operation::Noop {
+                optime,
+                ref message,
+                ..
+            } => {
+                let mut o = Document::new();
+                if let Some(ref message) = *message {
+                    o.insert("msg", message.clone());
+                }
+
+                (optime, "", o, None)
+            }
+            Operation::Insert {
+                optime,
+                ref namespace,
+                ref document,
+                ref query,
+                ..
+            } => (optime, namespace.as_str(), document.clone(), query.clone()),
+            Operation::Update {
+                optime,
+                ref namespace,
+                ref query,
+                ref update,
+                ..
+            } => (
+                optime,
+                namespace.as_str(),
+                update.clone(),
+                Some(query.clone()),
+            ),
+            Operation::Delete {
+                optime,
+                ref namespace,
+                ref query,
+                ..
+            } => (optime, namespace.as_str(), query.clone(), None),
+            Operation::Command {
+                optime,
+                ref namespace,
+                ref command,
+                ..
+            }
+            | Operation::CommitTransaction {
+                optime,
+                ref namespace,
+                ref command,
+                ..
+            }
+            | Operation::AbortTransaction {
+                optime,
+                ref namespace,
+                ref command,
+                ..
+            } => (optime, namespace.as_str(), command.clone(), None),
+            Operation::ApplyOps {
+                optime,
+                ref namespace,
+                ref operations,
+                partial_txn,
+                prepare,
+                ..
+            } => {
+                let mut o = Document::new();
+                o.insert(
+                    "applyOps",
+                    operations
+                        .iter()
+                        .map(|operation| Bson::Document(operation.to_document()))
+                        .collect::<Vec<Bson>>(),
+                );
+                if partial_txn {
+                    o.insert("partialTxn", true);
+                }
+                if prepare {
+                    o.insert("prepare", true);
+                }
+
+                (optime, namespace.as_str(), o, None)
+            }
+            Operation::Unknown { .. } => unreachable!("unknown operations return their document"),
+        };
+
+        document.insert("ts", optime);
+        document.insert("op", self.op_char());
+        document.insert("ns", namespace);
+        if let Some(uuid) = self.collection_uuid() {
+            document.insert("ui", uuid);
+        }
+        document.insert("o", o);
+        if let Some(o2) = o2 {
+            document.insert("o2", o2);
+        }
+        if let Some(pre_image) = self.pre_image() {
+            document.insert("preImage", pre_image.clone());
+        }
+
+        let metadata = self.metadata();
+        let mut lsid = Document::new();
+        if let Some(session_id) = metadata.session_id {
+            lsid.insert("id", session_id);
+        }
+        if let Some(uid) = self.uid().and_then(|uid| decode(uid).ok()) {
+            lsid.insert(
+                "uid",
+                bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: uid,
+                },
+            );
+        }
+        if !lsid.is_empty() {
+            document.insert("lsid", lsid);
+        }
+        metadata.write_to(&mut document);
+
+        document
+    }
+}
+
+/// An operation together with the raw oplog document it was parsed from.
+///
+/// This gives access to fields not modelled by `Operation` (e.g. `fromMigrate`, `wall` or `ui`)
+/// without re-querying or re-parsing the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedOperation {
+    operation: Operation,
+    raw: Document,
+}
+
+impl ParsedOperation {
+    /// Try to parse an operation from a BSON document, keeping the document.
+    pub fn new(raw: Document) -> Result<ParsedOperation> {
+        ParsedOperation::with_options(raw, &ParseOptions::default())
+    }
+
+    /// Try to parse an operation from a BSON document with the given parsing options, keeping the
+    /// document.
+    pub fn with_options(raw: Document, options: &ParseOptions) -> Result<ParsedOperation> {
+        let operation = Operation::with_options(&raw, options)?;
+
+        Ok(ParsedOperation::from_parts(operation, raw))
+    }
+
+    /// Returns an operation already parsed from the given document.
+    pub(crate) fn from_parts(operation: Operation, raw: Document) -> ParsedOperation {
+        ParsedOperation { operation, raw }
+    }
+
+    /// Returns the parsed operation.
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
+    /// Returns the raw oplog document the operation was parsed from.
+    pub fn raw(&self) -> &Document {
+        &self.raw
+    }
+
+    /// Returns the parsed operation, discarding the raw document.
+    pub fn into_operation(self) -> Operation {
+        self.operation
+    }
+}
+
+/// A type of oplog operation as recorded in the `op` field of the oplog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OpType {
+    /// A no-op (`"n"`).
+    Noop,
+    /// An insert (`"i"`).
+    Insert,
+    /// An update (`"u"`).
+    Update,
+    /// A delete (`"d"`).
+    Delete,
+    /// A command (`"c"`), including applyOps and transaction commands.
+    Command,
+}
+
+impl OpType {
+    /// Returns the MongoDB oplog op code of this type.
+    pub fn op_char(self) -> &'static str {
+        match self {
+            OpType::Noop => "n",
+            OpType::Insert => "i",
+            OpType::Update => "u",
+            OpType::Delete => "d",
+            OpType::Command => "c",
+        }
+    }
+}
+
+/// The kind of an `Operation`, as returned by `Operation::kind`.
+///
+/// This distinguishes every variant of `Operation` whereas `OpType` only distinguishes the op codes
+/// of the oplog, so `ApplyOps` and transaction commands are all `OpType::Command`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    /// An `Operation::Noop`.
+    Noop,
+    /// An `Operation::Insert`.
+    Insert,
+    /// An `Operation::Update`.
+    Update,
+    /// An `Operation::Delete`.
+    Delete,
+    /// An `Operation::Command`.
+    Command,
+    /// An `Operation::ApplyOps`.
+    ApplyOps,
+    /// An `Operation::CommitTransaction`.
+    CommitTransaction,
+    /// An `Operation::AbortTransaction`.
+    AbortTransaction,
+    /// An `Operation::Unknown`.
+    Unknown,
+}
+
+/// Options controlling how documents are parsed into operations.
+///
+/// # Example
+///
+/// ```
+/// use oplog::bson::{self, doc, Bson};
+/// use oplog::{Operation, ParseOptions};
+///
+/// let document = doc! {
+///     "ts": Bson::Timestamp(bson::Timestamp {
+///         time: 1479561394,
+///         increment: 0,
+///     }),
+///     "op": "c",
+///     "ns": "admin.$cmd",
+///     "o": {
+///         "applyOps": []
+///     }
+/// };
+/// let options = ParseOptions::default().max_depth(8);
+/// let operation = Operation::with_options(&document, &options)?;
+/// # Ok::<(), oplog::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    max_depth: usize,
+    tolerate_unknown: bool,
+    server_version: Option<ServerVersion>,
+}
+
+impl ParseOptions {
+    /// Set the maximum depth of nested applyOps commands.
+    ///
+    /// This is 32 by default and guards against exhausting the stack on pathological documents.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set whether unrecognized op codes are returned as `Operation::Unknown`.
+    ///
+    /// This is `false` by default, returning `Error::UnknownOperation` instead.
+    pub fn tolerate_unknown(mut self, tolerate_unknown: bool) -> Self {
+        self.tolerate_unknown = tolerate_unknown;
+        self
+    }
+
+    /// Set the version of the server the documents were written by.
+    ///
+    /// This determines how version-dependent fields are interpreted, e.g. updates are only
+    /// interpreted as `$v: 2` diffs when written by MongoDB 5.0 or later
+    /// (see `ServerVersion::supports_update_diffs`). By default, the version is inferred from each
+    /// document and `OplogBuilder::build` sets the version detected from the connected server.
+    pub fn server_version(mut self, server_version: ServerVersion) -> Self {
+        self.server_version = Some(server_version);
+        self
+    }
+
+    /// Returns the version of the server set with `server_version`, if any.
+    pub(crate) fn get_server_version(&self) -> Option<ServerVersion> {
+        self.server_version
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            max_depth: 32,
+            tolerate_unknown: false,
+            server_version: None,
+        }
+    }
+}
+
+impl TryFrom<&Document> for Operation {
+    type Error = Error;
+
+    fn try_from(document: &Document) -> Result<Operation> {
+        Operation::new(document)
+    }
+}
+
+impl TryFrom<Document> for Operation {
+    type Error = Error;
+
+    fn try_from(document: Document) -> Result<Operation> {
+        Operation::new(&document)
+    }
+}
+
+/// Operations are displayed on a single line by default. The alternate flag (`{:#}`) renders any
+/// embedded documents over multiple indented lines instead.
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alternate = f.alternate();
+
+        match *self {
+            Operation::Noop {
+                ref uid,
+                timestamp,
+                ref message,
+                ..
+            } => {
+                write!(
+                    f,
+                    "No-op #{} at {}: {:?}",
+                    display_uid(uid),
+                    timestamp,
+                    message
+                )
+            }
+            Operation::Insert {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ref document,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Insert #{} into {} at {}: {}",
+                    display_uid(uid),
+                    namespace,
+                    timestamp,
+                    DisplayDocument(document, alternate)
+                )
+            }
+            Operation::Update {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ref query,
+                ref update,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Update #{} {} with {} at {}: {}",
+                    display_uid(uid),
+                    namespace,
+                    DisplayDocument(query, alternate),
+                    timestamp,
+                    DisplayDocument(update, alternate)
+                )
+            }
+            Operation::Delete {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ref query,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Delete #{} from {} at {}: {}",
+                    display_uid(uid),
+                    namespace,
+                    timestamp,
+                    DisplayDocument(query, alternate)
+                )
+            }
+            Operation::Command {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ref command,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Command #{} {} at {}: {}",
+                    display_uid(uid),
+                    namespace,
+                    timestamp,
+                    DisplayDocument(command, alternate)
+                )
+            }
+            Operation::ApplyOps {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ref operations,
+                ..
+            } => {
+                write!(
+                    f,
+                    "ApplyOps #{} {} at {}: {} operations",
+                    display_uid(uid),
+                    namespace,
+                    timestamp,
+                    operations.len()
+                )
+            }
+            Operation::CommitTransaction {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ..
+            } => {
+                write!(
+                    f,
+                    "CommitTransaction #{} {} at {}",
+                    display_uid(uid),
+                    namespace,
+                    timestamp
+                )
+            }
+            Operation::AbortTransaction {
+                ref uid,
+                timestamp,
+                ref namespace,
+                ..
+            } => {
+                write!(
+                    f,
+                    "AbortTransaction #{} {} at {}",
+                    display_uid(uid),
+                    namespace,
+                    timestamp
+                )
+            }
+            Operation::Unknown {
+                ref uid,
+                timestamp,
+                ref op,
+                ref document,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Unknown #{} {} at {}: {}",
+                    display_uid(uid),
+                    op,
+                    timestamp,
+                    DisplayDocument(document, alternate)
+                )
+            }
+        }
+    }
+}
+
+/// A document displayed compactly or, with the alternate flag, over multiple indented lines.
+struct DisplayDocument<'a>(&'a Document, bool);
+
+impl<'a> fmt::Display for DisplayDocument<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.1 {
+            write!(f, "{:#}", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Convert a BSON timestamp into a UTC `DateTime`.
+///
+/// Note that the increment of a BSON timestamp is an ordinal for operations within the same second
+/// rather than a fraction of a second so it is discarded here (see the `optime` of an operation
+/// instead).
+fn timestamp_to_datetime(timestamp: bson::Timestamp) -> DateTime<Utc> {
+    let seconds = timestamp.time;
+
+    Utc.timestamp_opt(seconds as i64, 0).unwrap()
+}
+
+/// Return the base64-encoded session uid of a given document.
+///
+/// Operations written outside of a session (e.g. internal operations or those from servers
+/// predating sessions) have no `lsid` so this returns `None` rather than an error.
+fn get_uid(document: &Document) -> Result<Option<String>> {
+    let lsid = match document.get_document("lsid") {
+        Ok(lsid) => lsid,
+        Err(ValueAccessError::NotPresent) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    match lsid.get_binary_generic("uid") {
+        Ok(bytes) => Ok(Some(encode(bytes))),
+        Err(ValueAccessError::NotPresent) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Return the UUID of the collection of a given document.
+///
+/// Servers predating MongoDB 3.6 do not record the `ui` field so this returns `None` rather than an
+/// error.
+fn get_collection_uuid(document: &Document) -> Result<Option<bson::Uuid>> {
+    match document.get("ui") {
+        Some(Bson::Binary(ref binary)) => match binary.to_uuid() {
+            Ok(uuid) => Ok(Some(uuid)),
+            Err(_) => Err(ValueAccessError::UnexpectedType.into()),
+        },
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+        None => Ok(None),
+    }
+}
+
+/// Check that a namespace is a valid database followed by a collection, or `$cmd` for commands.
+fn validate_namespace(namespace: &str, command: bool) -> Result<()> {
+    let invalid = |problem: &str| Err(Error::Validation(format!("{:?} {}", namespace, problem)));
+
+    let (database, collection) = match namespace.split_once('.') {
+        Some(parts) => parts,
+        None => return invalid("is not a namespace of the form database.collection"),
+    };
+
+    if database.is_empty() || database.contains(|c| "/\\. \"$\0".contains(c)) {
+        invalid("has an invalid database name")
+    } else if collection.is_empty() || collection.contains('\0') {
+        invalid("has an invalid collection name")
+    } else if command != (collection == "$cmd") {
+        invalid("has the wrong collection for the operation")
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that a document identifies its document by `_id`.
+fn validate_id(document: &Document, description: &str) -> Result<()> {
+    if document.contains_key("_id") {
+        Ok(())
+    } else {
+        Err(Error::Validation(format!("{} has no _id", description)))
+    }
+}
+
+/// Return an optional embedded document field (e.g. `o2`) of a given document.
+fn get_optional_document(document: &Document, key: &str) -> Result<Option<Document>> {
+    match document.get(key) {
+        Some(Bson::Document(ref value)) => Ok(Some(value.clone())),
+        Some(_) => Err(ValueAccessError::UnexpectedType.into()),
+        None => Ok(None),
+    }
+}
+
+/// Returns the serialized BSON length of a document.
+fn bson_len(document: &Document) -> usize {
+    bson::to_vec(document).map_or(0, |bytes| bytes.len())
+}
+
+/// Render an optional uid for display.
+fn display_uid(uid: &Option<String>) -> &str {
+    uid.as_deref().unwrap_or("<none>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Oplog, StmtId};
+    use bson::doc;
+
+    fn ts(time: u32) -> Bson {
+        Bson::Timestamp(bson::Timestamp { time, increment: 0 })
+    }
+
+    fn noop() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479419535),
+            "op" : "n",
+            "ns" : "",
+            "o" : { "msg" : "initiating set" }
+        })
+        .unwrap()
+    }
+
+    fn insert() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap()
+    }
+
+    fn update() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479561033),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : { "$set" : { "foo" : "baz" } }
+        })
+        .unwrap()
+    }
+
+    fn delete() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479421186),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap()
+    }
+
+    fn command() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479553955),
+            "op" : "c",
+            "ns" : "test.$cmd",
+            "o" : { "create" : "foo" }
+        })
+        .unwrap()
+    }
+
+    fn apply_ops() -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : {
+                "applyOps" : [
+                    {
+                        "ts" : ts(1479561394),
+                        "op" : "i",
+                        "ns" : "foo.bar",
+                        "o" : { "_id" : 1, "foo" : "bar" }
+                    }
+                ]
+            }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn operation_converts_noops() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479419535 ,
+                increment: 0,
+            }),
+            "h" : (-2135725856567446411i64),
+            "v" : 2,
+            "op" : "n",
+            "ns" : "",
+            "o" : {
+                "msg" : "initiating set"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::Noop {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1479419535, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1479419535,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(-2135725856567446411),
+                    ..Metadata::default()
+                },
+                message: Some("initiating set".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn operation_converts_inserts() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment:0
+            }),
+            "h" : (-1742072865587022793i64),
+            "v" : 2,
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : {
+                "foo" : "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::Insert {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1479561394, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1479561394,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(-1742072865587022793),
+                    ..Metadata::default()
+                },
+                namespace: "foo.bar".into(),
+                collection_uuid: None,
+                document: doc! { "foo" : "bar" },
+                query: None,
+            }
+        );
+    }
+
+    #[test]
+    fn operation_converts_updates() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561033 ,
+                increment: 0,
+            }),
+            "h" : (3511341713062188019i64),
+            "v" : 2,
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : {
+                "_id" : 1
+            },
+            "o" : {
+                "$set" : {
+                    "foo" : "baz"
+                }
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::Update {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1479561033, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1479561033,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(3511341713062188019),
+                    ..Metadata::default()
+                },
+                namespace: "foo.bar".into(),
+                collection_uuid: None,
+                query: doc! { "_id" : 1 },
+                update: doc! { "$set" : { "foo" : "baz" } },
+                modification: UpdateModification::Operators(doc! { "$set" : { "foo" : "baz" } }),
+                pre_image: None,
+            }
+        );
+    }
+
+    #[test]
+    fn operation_converts_deletes() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479421186 ,
+                increment: 0,
+            }),
+            "h" : (-5457382347563537847i64),
+            "v" : 2,
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : {
+                "_id" : 1
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::Delete {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1479421186, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1479421186,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(-5457382347563537847),
+                    ..Metadata::default()
+                },
+                namespace: "foo.bar".into(),
+                collection_uuid: None,
+                query: doc! { "_id" : 1 },
+                pre_image: None,
+            }
+        );
+    }
+
+    #[test]
+    fn operation_converts_inserts_with_queries() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" },
+            "o2" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        match operation {
+            Operation::Insert { query, .. } => assert_eq!(query, Some(doc! { "_id" : 1 })),
+            _ => panic!("Expected insert."),
+        }
+        match insert() {
+            Operation::Insert { query, .. } => assert_eq!(query, None),
+            _ => panic!("Expected insert."),
+        }
+    }
+
+    #[test]
+    fn operation_converts_inlined_pre_images() {
+        let delete = Operation::new(&doc! {
+            "ts" : ts(1479421186),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 },
+            "preImage" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+        let update = Operation::new(&doc! {
+            "ts" : ts(1479421186),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : { "$set" : { "foo" : "baz" } },
+            "preImage" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(delete.pre_image(), Some(&doc! { "_id" : 1, "foo" : "bar" }));
+        assert_eq!(update.pre_image(), Some(&doc! { "_id" : 1, "foo" : "bar" }));
+        assert_eq!(update.documents().len(), 3);
+        assert_eq!(insert().pre_image(), None);
+    }
+
+    #[test]
+    fn operation_rejects_malformed_pre_images() {
+        let doc = doc! {
+            "ts" : ts(1479421186),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 },
+            "preImage" : "bar"
+        };
+
+        assert!(matches!(
+            Operation::new(&doc),
+            Err(Error::MissingField(ValueAccessError::UnexpectedType))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resolve_pre_image_returns_inlined_pre_images_without_a_lookup() {
+        let client = mongodb::Client::with_uri_str("mongodb://localhost:1")
+            .await
+            .unwrap();
+        let mut delete = Operation::new(&doc! {
+            "ts" : ts(1479421186),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 },
+            "preImage" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(
+            delete.resolve_pre_image(&client).await.unwrap(),
+            Some(&doc! { "_id" : 1, "foo" : "bar" })
+        );
+    }
+
+    #[test]
+    fn operation_converts_commands() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479553955 ,
+                increment: 0,
+            }),
+            "h" : (-7222343681970774929i64),
+            "v" : 2,
+            "op" : "c",
+            "ns" : "test.$cmd",
+            "o" : {
+                "create" : "foo"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::Command {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1479553955, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1479553955,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(-7222343681970774929),
+                    ..Metadata::default()
+                },
+                namespace: "test.$cmd".into(),
+                collection_uuid: None,
+                command: doc! { "create" : "foo" },
+            }
+        );
+    }
+
+    #[test]
+    fn operation_returns_unknown_operations() {
+        let doc = doc! { "op" : "x" };
+        let operation = Operation::new(&doc);
+
+        match operation {
+            Err(Error::UnknownOperation(op)) => assert_eq!(op, "x"),
+            _ => panic!("Expected unknown operation."),
+        }
+    }
+
+    #[test]
+    fn operation_tolerates_unknown_operations() {
+        let doc = doc! { "ts" : ts(1479561394), "op" : "x", "ns" : "foo.bar" };
+        let options = ParseOptions::default().tolerate_unknown(true);
+
+        match Operation::with_options(&doc, &options).unwrap() {
+            Operation::Unknown {
+                ref op,
+                ref document,
+                ..
+            } => {
+                assert_eq!(op, "x");
+                assert_eq!(document, &doc);
+            }
+            _ => panic!("Expected unknown operation."),
+        }
+
+        match Operation::new(&doc) {
+            Err(Error::UnknownOperation(op)) => assert_eq!(op, "x"),
+            _ => panic!("Expected unknown operation error."),
+        }
+    }
+
+    #[test]
+    fn command_kind_classifies_drop_database_operations() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "c",
+            "ns" : "foo.$cmd",
+            "o" : { "dropDatabase" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(
+            operation.command_kind(),
+            Some(CommandKind::DropDatabase {
+                database: "foo".into(),
+            })
+        );
+        assert_eq!(operation.database(), Some("foo"));
+    }
+
+    #[test]
+    fn command_kind_classifies_command_operations() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "c",
+            "ns" : "foo.$cmd",
+            "o" : { "dropIndexes" : "bar", "index" : "baz_1" }
+        })
+        .unwrap();
+
+        assert_eq!(
+            operation.command_kind(),
+            Some(CommandKind::DropIndexes {
+                collection: "bar".into(),
+                index: "baz_1".into(),
+            })
+        );
+        assert_eq!(insert().command_kind(), None);
+    }
+
+    #[test]
+    fn set_and_unset_fields_of_updates() {
+        let update = |o: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "u",
+                "ns" : "foo.bar",
+                "o2" : { "_id" : 1 },
+                "o" : o
+            })
+            .unwrap()
+        };
+
+        let legacy = update(doc! { "$set" : { "a" : 1 }, "$unset" : { "b" : true } });
+        assert_eq!(legacy.set_fields(), Some(doc! { "a" : 1 }));
+        assert_eq!(legacy.unset_fields(), Some(vec!["b"]));
+
+        let diff = update(doc! { "$v" : 2, "diff" : { "u" : { "a" : 1 }, "d" : { "b" : false } } });
+        assert_eq!(diff.set_fields(), Some(doc! { "a" : 1 }));
+        assert_eq!(diff.unset_fields(), Some(vec!["b"]));
+
+        let replacement = update(doc! { "_id" : 1, "a" : 1 });
+        assert_eq!(replacement.set_fields(), None);
+        assert_eq!(replacement.unset_fields(), None);
+        assert_eq!(insert().set_fields(), None);
+    }
+
+    #[test]
+    fn is_replacement_distinguishes_replacements_from_modifications() {
+        let update = |o: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "u",
+                "ns" : "foo.bar",
+                "o2" : { "_id" : 1 },
+                "o" : o
+            })
+            .unwrap()
+        };
+
+        assert!(update(doc! { "_id" : 1, "foo" : "baz" }).is_replacement());
+        assert!(!update(doc! { "$set" : { "foo" : "baz" } }).is_replacement());
+        assert!(!update(doc! { "$v" : 2, "diff" : { "u" : { "foo" : "baz" } } }).is_replacement());
+        assert!(!insert().is_replacement());
+    }
+
+    #[test]
+    fn is_ddl_for_schema_changing_commands() {
+        let command = |o: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "c",
+                "ns" : "foo.$cmd",
+                "o" : o
+            })
+            .unwrap()
+        };
+
+        assert!(command(doc! { "create" : "bar" }).is_ddl());
+        assert!(command(doc! { "drop" : "bar" }).is_ddl());
+        assert!(
+            command(doc! { "createIndexes" : "bar", "key" : { "a" : 1 }, "name" : "a_1" }).is_ddl()
+        );
+        assert!(command(doc! { "renameCollection" : "foo.bar", "to" : "foo.baz" }).is_ddl());
+        assert!(command(doc! { "collMod" : "bar", "validator" : {} }).is_ddl());
+        assert!(command(doc! { "dropDatabase" : 1 }).is_ddl());
+        assert!(command(doc! { "convertToCapped" : "bar", "size" : 4096 }).is_ddl());
+        assert!(!command(doc! { "emptycapped" : "bar" }).is_ddl());
+        assert!(!command(doc! { "foo" : "bar" }).is_ddl());
+    }
+
+    #[test]
+    fn is_ddl_is_false_for_data_changes() {
+        assert!(!insert().is_ddl());
+        assert!(!apply_ops().is_ddl());
+        assert!(!Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "n",
+            "ns" : "",
+            "o" : { "msg" : "periodic noop" }
+        })
+        .unwrap()
+        .is_ddl());
+    }
+
+    #[test]
+    fn noop_kind_classifies_no_op_messages() {
+        let noop = |o: Document| {
+            Operation::new(&doc! { "ts" : ts(1479561394), "op" : "n", "ns" : "", "o" : o }).unwrap()
+        };
+
+        assert_eq!(
+            noop(doc! { "msg" : "initiating set" }).noop_kind(),
+            Some(NoopKind::InitiatingSet)
+        );
+        assert_eq!(
+            noop(doc! { "msg" : "something else" }).noop_kind(),
+            Some(NoopKind::Other("something else".into()))
+        );
+        assert_eq!(noop(doc! {}).noop_kind(), None);
+        assert_eq!(noop(doc! { "msg" : "new primary" }).namespace(), None);
+        assert_eq!(insert().noop_kind(), None);
+    }
+
+    #[test]
+    fn documents_returns_the_query_and_update_of_updates() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : { "$set" : { "foo" : "baz" } }
+        })
+        .unwrap();
+
+        assert_eq!(
+            operation.documents(),
+            vec![&doc! { "_id" : 1 }, &doc! { "$set" : { "foo" : "baz" } }]
+        );
+    }
+
+    #[test]
+    fn documents_recurses_into_apply_ops() {
+        assert_eq!(
+            apply_ops().documents(),
+            vec![&doc! { "_id" : 1, "foo" : "bar" }]
+        );
+    }
+
+    #[test]
+    fn map_documents_recurses_into_apply_ops() {
+        let mut operation = apply_ops();
+
+        operation.map_documents(|document| {
+            if let Ok(foo) = document.get_str("foo") {
+                let upper = foo.to_uppercase();
+                document.insert("foo", upper);
+            }
+        });
+
+        assert_eq!(
+            operation.documents(),
+            vec![&doc! { "_id" : 1, "foo" : "BAR" }]
+        );
+    }
+
+    #[test]
+    fn size_bytes_estimates_the_size_of_inserts() {
+        // The 27 byte document `{ "_id" : 1, "foo" : "bar" }` plus the namespace `foo.bar`.
+        assert_eq!(insert().size_bytes(), 27 + 7);
+    }
+
+    #[test]
+    fn size_bytes_includes_pre_images() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479421186),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 },
+            "preImage" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap();
+
+        // The 27 byte pre-image `{ "_id" : 1, "foo" : "bar" }` on top of the delete.
+        assert_eq!(operation.size_bytes(), delete().size_bytes() + 27);
+    }
+
+    #[test]
+    fn size_bytes_sums_apply_ops() {
+        assert_eq!(
+            apply_ops().size_bytes(),
+            insert().size_bytes() + "admin.$cmd".len()
+        );
+    }
+
+    fn upsert(query: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o2" : query,
+            "o" : { "_id" : 1, "foo" : "bar" }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn documents_includes_the_query_of_upserts() {
+        assert_eq!(
+            upsert(doc! { "_id" : 1, "shard" : 2 }).documents(),
+            vec![
+                &doc! { "_id" : 1, "foo" : "bar" },
+                &doc! { "_id" : 1, "shard" : 2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn content_eq_compares_the_query_of_upserts() {
+        let operation = upsert(doc! { "_id" : 1, "shard" : 2 });
+
+        assert!(operation.content_eq(&upsert(doc! { "_id" : 1, "shard" : 2 })));
+        assert!(!operation.content_eq(&upsert(doc! { "_id" : 1, "shard" : 3 })));
+        assert!(!operation.content_eq(&insert()));
+    }
+
+    #[test]
+    fn map_documents_maps_the_query_of_upserts() {
+        let mut operation = upsert(doc! { "_id" : 1, "foo" : "bar" });
+
+        operation.map_documents(|document| {
+            document.insert("foo", "baz");
+        });
+
+        assert_eq!(
+            operation.documents(),
+            vec![
+                &doc! { "_id" : 1, "foo" : "baz" },
+                &doc! { "_id" : 1, "foo" : "baz" }
+            ]
+        );
+    }
+
+    #[test]
+    fn size_bytes_includes_the_query_of_upserts() {
+        // The 14 byte document `{ "_id" : 1 }` on top of the insert.
+        assert_eq!(
+            upsert(doc! { "_id" : 1 }).size_bytes(),
+            insert().size_bytes() + 14
+        );
+    }
+
+    #[test]
+    fn sort_key_orders_operations_by_oplog_position() {
+        let operation = |time, increment| {
+            Operation::new(&doc! {
+                "ts" : Bson::Timestamp(bson::Timestamp { time, increment }),
+                "op" : "n",
+                "ns" : "",
+                "o" : {}
+            })
+            .unwrap()
+        };
+        let mut operations = [operation(2, 0), operation(1, 2), operation(1, 1)];
+
+        operations.sort_by_key(Operation::sort_key);
+
+        assert_eq!(
+            operations
+                .iter()
+                .map(Operation::sort_key)
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (1, 2), (2, 0)]
+        );
+        assert!(operation(1, 1).sort_key() < operation(1, 2).sort_key());
+    }
+
+    #[test]
+    fn operation_converts_documents_with_try_from() {
+        use std::convert::TryInto;
+
+        let doc = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        };
+
+        let borrowed: Operation = (&doc).try_into().unwrap();
+        let owned = Operation::try_from(doc).unwrap();
+
+        assert_eq!(borrowed, owned);
+        assert!(owned.is_insert());
+        assert!(matches!(
+            Operation::try_from(doc! { "op" : "i" }),
+            Err(Error::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn operation_converts_raw_bytes() {
+        let document = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        };
+        let mut bytes = Vec::new();
+        document.to_writer(&mut bytes).unwrap();
+
+        assert_eq!(Operation::from_slice(&bytes).unwrap(), insert());
+    }
+
+    #[test]
+    fn operation_returns_deserialize_errors() {
+        match Operation::from_slice(&[5, 0, 0]) {
+            Err(Error::Deserialize(_)) => {}
+            _ => panic!("Expected deserialize error."),
+        }
+    }
+
+    #[test]
+    fn operation_inherits_the_timestamp_and_uid_of_apply_ops() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "lsid" : { "uid" : Bson::Binary(bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes: vec![1, 2, 3],
+            }) },
+            "o" : {
+                "applyOps" : [
+                    { "op" : "i", "ns" : "foo.bar", "o" : { "_id" : 1 } },
+                    { "ts" : ts(1479561394), "op" : "d", "ns" : "foo.bar", "o" : { "_id" : 2 } }
+                ]
+            }
+        })
+        .unwrap();
+
+        let operations = operation.flatten();
+
+        assert_eq!(operations[0].timestamp(), operation.timestamp());
+        assert_eq!(operations[0].uid(), Some("AQID"));
+        assert_eq!(operations[1].timestamp().timestamp(), 1479561394);
+        assert_eq!(operations[1].uid(), Some("AQID"));
+    }
+
+    #[test]
+    fn operation_parses_minimal_operations_of_apply_ops_in_order() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1483789052),
+            "h" : 0i64,
+            "v" : 2,
+            "wall" : bson::DateTime::from_millis(1483789052123),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : {
+                "applyOps" : [
+                    { "op" : "i", "ns" : "foo.bar", "o" : { "_id" : 1 } },
+                    {
+                        "op" : "u",
+                        "ns" : "foo.bar",
+                        "o2" : { "_id" : 1 },
+                        "o" : { "$set" : { "a" : 1 } }
+                    },
+                    { "op" : "d", "ns" : "foo.bar", "o" : { "_id" : 1 } }
+                ]
+            }
+        })
+        .unwrap();
+
+        let operations = operation.flatten();
+        let ops: Vec<&str> = operations
+            .iter()
+            .map(|operation| operation.op_char())
+            .collect();
+
+        assert_eq!(ops, vec!["i", "u", "d"]);
+        for inner in operations {
+            assert_eq!(inner.oplog_ts(), operation.oplog_ts());
+            assert_eq!(inner.metadata().version, Some(2));
+            assert_eq!(inner.metadata().wall_clock, operation.metadata().wall_clock);
+        }
+    }
+
+    #[test]
+    fn operation_preserves_the_oplog_version() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "v" : 2,
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(operation.metadata().version, Some(2));
+        assert_eq!(insert().metadata().version, None);
+    }
+
+    #[test]
+    fn operation_converts_migrated_inserts() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "fromMigrate" : true,
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert!(operation.metadata().from_migrate);
+        assert!(!insert().metadata().from_migrate);
+    }
+
+    #[test]
+    fn operation_displays_compactly_by_default() {
+        assert_eq!(
+            format!("{}", insert()),
+            "Insert #<none> into foo.bar at 2016-11-19 13:16:34 UTC: { \"_id\": 1, \"foo\": \"bar\" }"
+        );
+    }
+
+    #[test]
+    fn operation_displays_documents_over_multiple_lines_when_alternate() {
+        assert_eq!(
+            format!("{:#}", insert()),
+            "Insert #<none> into foo.bar at 2016-11-19 13:16:34 UTC: {\n  \"_id\": 1,\n  \"foo\": \"bar\"\n}"
+        );
+    }
+
+    #[test]
+    fn parse_batch_separates_operations_and_errors() {
+        let valid = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        };
+        let documents = [
+            valid.clone(),
+            doc! { "op" : "x" },
+            valid,
+            doc! { "foo" : "bar" },
+        ];
+
+        let (operations, errors) = Operation::parse_batch(&documents);
+
+        assert_eq!(operations, vec![insert(), insert()]);
+        assert_eq!(errors.len(), 2);
+        match errors[0] {
+            (1, Error::UnknownOperation(ref op)) => assert_eq!(op, "x"),
+            _ => panic!("Expected unknown operation at 1."),
+        }
+        match errors[1] {
+            (3, Error::MissingField(_)) => {}
+            _ => panic!("Expected missing field at 3."),
+        }
+    }
+
+    #[test]
+    fn object_id_returns_the_id_of_each_operation() {
+        let oid = bson::oid::ObjectId::new();
+        let update = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : oid },
+            "o" : { "$set" : { "foo" : "baz" } }
+        })
+        .unwrap();
+
+        assert_eq!(insert().object_id(), Some(&Bson::Int32(1)));
+        assert_eq!(update.object_id(), Some(&Bson::ObjectId(oid)));
+        assert_eq!(delete().object_id(), Some(&Bson::Int32(1)));
+        assert_eq!(noop().object_id(), None);
+        assert_eq!(command().object_id(), None);
+    }
+
+    #[test]
+    fn object_id_returns_none_without_an_id() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(operation.object_id(), None);
+    }
+
+    #[test]
+    fn document_key_returns_compound_shard_keys() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "region" : "eu", "customer" : 42, "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(
+            operation.document_key(),
+            Some(&doc! { "region" : "eu", "customer" : 42, "_id" : 1 })
+        );
+    }
+
+    #[test]
+    fn document_key_returns_the_query_of_updates() {
+        match update() {
+            ref operation @ Operation::Update { ref query, .. } => {
+                assert_eq!(operation.document_key(), Some(query));
+            }
+            _ => panic!("Expected update."),
+        }
+        assert_eq!(insert().document_key(), None);
+    }
+
+    #[test]
+    fn oplog_ts_preserves_the_increment() {
+        let ts = bson::Timestamp {
+            time: 1479561394,
+            increment: 7,
+        };
+        let operation = Operation::new(&doc! {
+            "ts" : ts,
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(operation.oplog_ts(), ts);
+        assert_eq!(
+            Oplog::builder().start_at(operation.oplog_ts()).query(),
+            Some(doc! { "ts" : { "$gte" : ts } })
+        );
+    }
+
+    #[test]
+    fn operation_returns_missing_fields() {
+        let doc = doc! { "foo" : "bar" };
+        let operation = Operation::new(&doc);
+
+        match operation {
+            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::NotPresent),
+            _ => panic!("Expected missing field."),
+        }
+    }
+
+    #[test]
+    fn operation_returns_apply_ops() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1483789052 ,
+                increment: 0,
+            }),
+            "h" : (-3262249347345468996i64),
+            "v" : 2,
+            "op" : "c",
+            "ns" : "foo.$cmd",
+            "o" : {
+                "applyOps" : [
+                    {
+                        "ts" : Bson::Timestamp(bson::Timestamp {
+                            time: 1479561394 ,
+                            increment: 0,
+                        }),
+                        "t" : 2,
+                        "h" : (-1742072865587022793i64),
+                        "op" : "i",
+                        "ns" : "foo.bar",
+                        "o" : {
+                            "_id" : 1,
+                            "foo" : "bar"
+                        }
+                    }
+                ]
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::ApplyOps {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1483789052, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1483789052,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    version: Some(2),
+                    hash: Some(-3262249347345468996),
+                    ..Metadata::default()
+                },
+                namespace: "foo.$cmd".into(),
+                operations: vec![Operation::Insert {
+                    uid: None,
+                    timestamp: Utc.timestamp_opt(1479561394, 0).unwrap(),
+                    optime: bson::Timestamp {
+                        time: 1479561394,
+                        increment: 0,
+                    },
+                    metadata: Metadata {
+                        version: Some(2),
+                        hash: Some(-1742072865587022793),
+                        ..Metadata::default()
+                    },
+                    namespace: "foo.bar".into(),
+                    collection_uuid: None,
+                    document: doc! { "_id" : 1, "foo" : "bar" },
+                    query: None,
+                }],
+                partial_txn: false,
+                prepare: false,
+            }
+        );
+    }
+
+    #[test]
+    fn operation_ignores_increment_in_timestamp() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment: 5,
+            }),
+            "h" : (-1742072865587022793i64),
+            "v" : 2,
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : {
+                "foo" : "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::Insert {
+                timestamp, optime, ..
+            } => {
+                assert_eq!(timestamp, Utc.timestamp_opt(1479561394, 0).unwrap());
+                assert_eq!(optime.increment, 5);
+            }
+            _ => panic!("Expected insert."),
+        }
+    }
+
+    #[test]
+    fn operation_preserves_order_within_the_same_second() {
+        let first = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment: 1,
+            }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : {
+                "_id" : 1
+            }
+        };
+        let second = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment: 2,
+            }),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : {
+                "_id" : 1
+            }
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bson::doc;
+        match (
+            Operation::new(&first).unwrap(),
+            Operation::new(&second).unwrap(),
+        ) {
+            (
+                Operation::Insert {
+                    timestamp: first_timestamp,
+                    optime: first_optime,
+                    ..
+                },
+                Operation::Delete {
+                    timestamp: second_timestamp,
+                    optime: second_optime,
+                    ..
+                },
+            ) => {
+                assert_eq!(first_timestamp, second_timestamp);
+                assert!(first_optime < second_optime);
+            }
+            _ => panic!("Expected an insert followed by a delete."),
+        }
+    }
+
+    #[test]
+    fn operation_converts_legacy_documents_without_lsid() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment: 0,
+            }),
+            "h" : (-1742072865587022793i64),
+            "v" : 2,
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : {
+                "foo" : "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::Insert { ref uid, .. } => assert_eq!(*uid, None),
+            _ => panic!("Expected insert."),
+        }
+        assert!(operation
+            .to_string()
+            .starts_with("Insert #<none> into foo.bar"));
+    }
+
+    #[test]
+    fn operation_converts_session_uids() {
+        let doc = doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp {
+                time: 1479561394 ,
+                increment: 0,
+            }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "lsid" : {
+                "uid" : Bson::Binary(bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: vec![1, 2, 3],
+                })
+            },
+            "o" : {
+                "foo" : "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::Insert { ref uid, .. } => assert_eq!(uid.as_deref(), Some("AQID")),
+            _ => panic!("Expected insert."),
+        }
+        assert!(operation
+            .to_string()
+            .starts_with("Insert #AQID into foo.bar"));
+    }
+
+    #[test]
+    fn namespace_returns_the_namespace_of_each_operation() {
+        assert_eq!(noop().namespace(), None);
+        assert_eq!(insert().namespace(), Some("foo.bar"));
+        assert_eq!(update().namespace(), Some("foo.bar"));
+        assert_eq!(delete().namespace(), Some("foo.bar"));
+        assert_eq!(command().namespace(), Some("test.$cmd"));
+        assert_eq!(apply_ops().namespace(), Some("admin.$cmd"));
+    }
+
+    #[test]
+    fn database_and_collection_split_the_namespace() {
+        assert_eq!(insert().database(), Some("foo"));
+        assert_eq!(insert().collection(), Some("bar"));
+        assert_eq!(noop().database(), None);
+        assert_eq!(noop().collection(), None);
+    }
+
+    #[test]
+    fn database_and_collection_handle_commands() {
+        assert_eq!(command().database(), Some("test"));
+        assert_eq!(command().collection(), Some("$cmd"));
+    }
+
+    #[test]
+    fn database_and_collection_handle_dotted_collections() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "db.foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(operation.database(), Some("db"));
+        assert_eq!(operation.collection(), Some("foo.bar"));
+    }
+
+    #[test]
+    fn timestamp_returns_the_time_of_each_operation() {
+        assert_eq!(
+            noop().timestamp(),
+            Utc.timestamp_opt(1479419535, 0).unwrap()
+        );
+        assert_eq!(
+            insert().timestamp(),
+            Utc.timestamp_opt(1479561394, 0).unwrap()
+        );
+        assert_eq!(
+            update().timestamp(),
+            Utc.timestamp_opt(1479561033, 0).unwrap()
+        );
+        assert_eq!(
+            delete().timestamp(),
+            Utc.timestamp_opt(1479421186, 0).unwrap()
+        );
+        assert_eq!(
+            command().timestamp(),
+            Utc.timestamp_opt(1479553955, 0).unwrap()
+        );
+        assert_eq!(
+            apply_ops().timestamp(),
+            Utc.timestamp_opt(1483789052, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn uid_returns_the_uid_of_each_operation() {
+        let lsid = doc! {
+            "uid" : Bson::Binary(bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes: vec![1, 2, 3],
+            })
+        };
+        let documents = vec![
+            doc! { "ts" : ts(1), "op" : "n", "ns" : "", "o" : {}, "lsid" : lsid.clone() },
+            doc! { "ts" : ts(1), "op" : "i", "ns" : "a.b", "o" : {}, "lsid" : lsid.clone() },
+            doc! { "ts" : ts(1), "op" : "u", "ns" : "a.b", "o" : {}, "o2" : {}, "lsid" : lsid.clone() },
+            doc! { "ts" : ts(1), "op" : "d", "ns" : "a.b", "o" : {}, "lsid" : lsid.clone() },
+            doc! { "ts" : ts(1), "op" : "c", "ns" : "a.$cmd", "o" : {}, "lsid" : lsid.clone() },
+            doc! { "ts" : ts(1), "op" : "c", "ns" : "a.$cmd", "o" : { "applyOps" : [] }, "lsid" : lsid },
+        ];
+
+        for document in documents {
+            assert_eq!(Operation::new(&document).unwrap().uid(), Some("AQID"));
+        }
+        assert_eq!(insert().uid(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn operation_round_trips_through_json() {
+        for operation in [noop(), insert(), update(), delete(), command(), apply_ops()] {
+            let json = serde_json::to_string(&operation).unwrap();
+
+            assert_eq!(serde_json::from_str::<Operation>(&json).unwrap(), operation);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn operation_serializes_timestamps_as_rfc3339() {
+        let json = serde_json::to_value(insert()).unwrap();
+
+        assert_eq!(json["Insert"]["timestamp"], "2016-11-19T13:16:34Z");
+    }
+
+    #[test]
+    fn operation_converts_diff_updates() {
+        let doc = doc! {
+            "ts" : ts(1479561033),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : {
+                "$v" : 2,
+                "diff" : { "u" : { "foo" : "baz" } }
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::Update { modification, .. } => assert_eq!(
+                modification,
+                UpdateModification::Diff {
+                    set: doc! { "foo" : "baz" },
+                    unset: vec![],
+                    insert: doc! {},
+                }
+            ),
+            _ => panic!("Expected update."),
+        }
+    }
+
+    #[test]
+    fn operation_converts_retryable_writes() {
+        let doc = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "txnNumber" : 5i64,
+            "stmtId" : 0,
+            "o" : { "_id" : 1 }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(operation.metadata().txn_number, Some(5));
+        assert_eq!(operation.metadata().stmt_id, Some(StmtId::Single(0)));
+    }
+
+    #[test]
+    fn operation_converts_commit_transactions() {
+        let doc = doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "txnNumber" : 1i64,
+            "o" : {
+                "commitTransaction" : 1,
+                "commitTimestamp" : ts(1483789051)
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::CommitTransaction {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1483789052, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1483789052,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    txn_number: Some(1),
+                    ..Metadata::default()
+                },
+                namespace: "admin.$cmd".into(),
+                commit_timestamp: Some(bson::Timestamp {
+                    time: 1483789051,
+                    increment: 0,
+                }),
+                command: doc! {
+                    "commitTransaction" : 1,
+                    "commitTimestamp" : ts(1483789051)
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn operation_converts_abort_transactions() {
+        let doc = doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "txnNumber" : 1i64,
+            "o" : { "abortTransaction" : 1 }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(
+            operation,
+            Operation::AbortTransaction {
+                uid: None,
+                timestamp: Utc.timestamp_opt(1483789052, 0).unwrap(),
+                optime: bson::Timestamp {
+                    time: 1483789052,
+                    increment: 0,
+                },
+                metadata: Metadata {
+                    txn_number: Some(1),
+                    ..Metadata::default()
+                },
+                namespace: "admin.$cmd".into(),
+                command: doc! { "abortTransaction" : 1 },
+            }
+        );
+    }
+
+    #[test]
+    fn flatten_returns_non_apply_ops_operations() {
+        let operation = insert();
+
+        assert_eq!(operation.flatten(), vec![&operation]);
+    }
+
+    #[test]
+    fn flatten_expands_nested_apply_ops() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : {
+                "applyOps" : [
+                    {
+                        "ts" : ts(1479561394),
+                        "op" : "i",
+                        "ns" : "foo.bar",
+                        "o" : { "_id" : 1 }
+                    },
+                    {
+                        "ts" : ts(1479561394),
+                        "op" : "c",
+                        "ns" : "admin.$cmd",
+                        "o" : {
+                            "applyOps" : [
+                                {
+                                    "ts" : ts(1479561394),
+                                    "op" : "u",
+                                    "ns" : "foo.bar",
+                                    "o2" : { "_id" : 1 },
+                                    "o" : { "$set" : { "foo" : "baz" } }
+                                },
+                                {
+                                    "ts" : ts(1479561394),
+                                    "op" : "d",
+                                    "ns" : "foo.bar",
+                                    "o" : { "_id" : 1 }
+                                }
+                            ]
+                        }
+                    }
+                ]
+            }
+        })
+        .unwrap();
+
+        let kinds: Vec<_> = operation
+            .flatten()
+            .into_iter()
+            .map(|operation| match *operation {
+                Operation::Insert { .. } => "i",
+                Operation::Update { .. } => "u",
+                Operation::Delete { .. } => "d",
+                _ => "?",
+            })
+            .collect();
+
+        assert_eq!(kinds, vec!["i", "u", "d"]);
+    }
+
+    #[test]
+    fn operation_returns_max_depth_exceeded() {
+        let mut doc = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        };
+        for _ in 0..33 {
+            doc = doc! {
+                "ts" : ts(1483789052),
+                "op" : "c",
+                "ns" : "admin.$cmd",
+                "o" : { "applyOps" : [doc] }
+            };
+        }
+
+        match Operation::new(&doc) {
+            Err(Error::MaxDepthExceeded) => {}
+            _ => panic!("Expected max depth exceeded."),
+        }
+    }
+
+    #[test]
+    fn operation_respects_configured_max_depth() {
+        let doc = doc! {
+            "ts" : ts(1483789052),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : {
+                "applyOps" : [
+                    {
+                        "ts" : ts(1479561394),
+                        "op" : "i",
+                        "ns" : "foo.bar",
+                        "o" : { "_id" : 1 }
+                    }
+                ]
+            }
+        };
+
+        assert!(Operation::with_options(&doc, &ParseOptions::default().max_depth(1)).is_ok());
+
+        match Operation::with_options(&doc, &ParseOptions::default().max_depth(0)) {
+            Err(Error::MaxDepthExceeded) => {}
+            _ => panic!("Expected max depth exceeded."),
+        }
+    }
+
+    #[cfg(feature = "extended-json")]
+    #[test]
+    fn operation_renders_canonical_extended_json() {
+        let id = bson::oid::ObjectId::parse_str("507f1f77bcf86cd799439011").unwrap();
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : id, "foo" : "bar" }
+        })
+        .unwrap();
+
+        let json = operation.to_extended_json();
+
+        assert_eq!(json["op"], "i");
+        assert_eq!(json["ns"], "foo.bar");
+        assert_eq!(
+            json["ts"],
+            serde_json::json!({ "$timestamp": { "t": 1479561394, "i": 0 } })
+        );
+        assert_eq!(
+            json["o"]["_id"],
+            serde_json::json!({ "$oid": "507f1f77bcf86cd799439011" })
+        );
+        assert_eq!(json["o"]["foo"], "bar");
+    }
+
+    #[cfg(feature = "extended-json")]
+    #[test]
+    fn operation_renders_updates_and_apply_ops_as_extended_json() {
+        let json = update().to_extended_json();
+
+        assert_eq!(json["o2"]["_id"], serde_json::json!({ "$numberInt": "1" }));
+
+        let json = apply_ops().to_extended_json();
+
+        assert_eq!(json["o"]["applyOps"][0]["op"], "i");
+    }
+
+    #[test]
+    fn operation_interprets_updates_for_a_forced_server_version() {
+        let document = doc! {
+            "ts" : ts(1479561033),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o2" : { "_id" : 1 },
+            "o" : { "$v" : 2, "diff" : { "u" : { "foo" : "baz" } } }
+        };
+
+        let options = ParseOptions::default().server_version(ServerVersion::new(6, 0, 4));
+        match Operation::with_options(&document, &options).unwrap() {
+            Operation::Update { modification, .. } => assert_eq!(
+                modification,
+                UpdateModification::Diff {
+                    set: doc! { "foo" : "baz" },
+                    unset: vec![],
+                    insert: doc! {},
+                }
+            ),
+            _ => panic!("Expected update."),
+        }
+
+        let options = ParseOptions::default().server_version(ServerVersion::new(4, 4, 18));
+        match Operation::with_options(&document, &options).unwrap() {
+            Operation::Update { modification, .. } => assert_eq!(
+                modification,
+                UpdateModification::Operators(
+                    doc! { "$v" : 2, "diff" : { "u" : { "foo" : "baz" } } }
+                )
+            ),
+            _ => panic!("Expected update."),
+        }
+    }
+
+    fn assert_round_trips(operation: &Operation) {
+        let options = ParseOptions::default().tolerate_unknown(true);
+        let document = operation.to_document();
+
+        assert_eq!(
+            Operation::with_options(&document, &options).unwrap(),
+            *operation,
+            "{:?}",
+            document
+        );
+    }
+
+    #[test]
+    fn to_document_round_trips_each_variant() {
+        let session_id = bson::Uuid::new();
+        let operations = vec![
+            noop(),
+            insert(),
+            update(),
+            delete(),
+            command(),
+            apply_ops(),
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "c",
+                "ns" : "admin.$cmd",
+                "txnNumber" : 1i64,
+                "lsid" : { "id" : session_id },
+                "prevOpTime" : { "ts" : bson::Timestamp { time: 1479561393, increment: 1 } },
+                "o" : {
+                    "commitTransaction" : 1,
+                    "commitTimestamp" : bson::Timestamp { time: 1479561393, increment: 2 }
+                }
+            })
+            .unwrap(),
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "c",
+                "ns" : "admin.$cmd",
+                "o" : { "abortTransaction" : 1 }
+            })
+            .unwrap(),
+            Operation::with_options(
+                &doc! { "ts" : ts(1479561394), "op" : "x", "ns" : "foo.bar" },
+                &ParseOptions::default().tolerate_unknown(true),
+            )
+            .unwrap(),
+        ];
+
+        for operation in &operations {
+            assert_round_trips(operation);
+        }
+    }
+
+    #[test]
+    fn to_document_round_trips_metadata() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "h" : -1742072865587022793i64,
+            "v" : 2,
+            "op" : "d",
+            "ns" : "foo.bar",
+            "ui" : bson::Uuid::new(),
+            "wall" : bson::DateTime::from_millis(1479561394123),
+            "txnNumber" : 3i64,
+            "stmtId" : 1,
+            "fromMigrate" : true,
+            "lsid" : {
+                "id" : bson::Uuid::new(),
+                "uid" : Bson::Binary(bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: vec![1, 2, 3],
+                })
+            },
+            "preImageOpTime" : { "ts" : bson::Timestamp { time: 1479561394, increment: 1 } },
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_round_trips(&operation);
+        assert_eq!(
+            operation.to_document().get_i64("h").unwrap(),
+            -1742072865587022793
+        );
+    }
 
     #[test]
-    fn operation_converts_noops() {
-        let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1479419535 ,
-                increment: 0,
-            }),
-            "h" : (-2135725856567446411i64),
-            "v" : 2,
-            "op" : "n",
-            "ns" : "",
+    fn to_document_round_trips_updates_and_transactions() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "txnNumber" : 0i64,
+            "lsid" : { "id" : bson::Uuid::new() },
             "o" : {
-                "msg" : "initiating set"
+                "applyOps" : [
+                    {
+                        "op" : "u",
+                        "ns" : "foo.bar",
+                        "o2" : { "_id" : 1 },
+                        "o" : { "$v" : 2, "diff" : { "u" : { "foo" : "baz" } } }
+                    },
+                    {
+                        "op" : "i",
+                        "ns" : "foo.bar",
+                        "o2" : { "_id" : 2 },
+                        "o" : { "_id" : 2, "foo" : "bar" }
+                    }
+                ],
+                "partialTxn" : true
+            }
+        })
+        .unwrap();
+
+        assert_round_trips(&operation);
+    }
+
+    #[test]
+    fn predicates_match_only_their_own_variant() {
+        type Predicate = fn(&Operation) -> bool;
+
+        let predicates: [Predicate; 6] = [
+            Operation::is_noop,
+            Operation::is_insert,
+            Operation::is_update,
+            Operation::is_delete,
+            Operation::is_command,
+            Operation::is_apply_ops,
+        ];
+        let operations = [noop(), insert(), update(), delete(), command(), apply_ops()];
+
+        for (i, operation) in operations.iter().enumerate() {
+            for (j, predicate) in predicates.iter().enumerate() {
+                assert_eq!(predicate(operation), i == j, "{} {}", i, j);
             }
+        }
+
+        let commit = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : { "commitTransaction" : 1 }
+        })
+        .unwrap();
+
+        assert!(predicates.iter().all(|predicate| !predicate(&commit)));
+    }
+
+    #[test]
+    fn op_char_returns_the_op_code_of_each_operation() {
+        assert_eq!(noop().op_char(), "n");
+        assert_eq!(insert().op_char(), "i");
+        assert_eq!(update().op_char(), "u");
+        assert_eq!(delete().op_char(), "d");
+        assert_eq!(command().op_char(), "c");
+        assert_eq!(apply_ops().op_char(), "c");
+    }
+
+    #[test]
+    fn namespace_matches_globs() {
+        let insert = |ns: &str| {
+            Operation::new(&doc! { "ts" : ts(1479561394), "op" : "i", "ns" : ns, "o" : {} })
+                .unwrap()
+        };
+
+        assert!(insert("sales.orders").namespace_matches("sales.*"));
+        assert!(!insert("crm.orders").namespace_matches("sales.*"));
+        assert!(insert("crm.orders").namespace_matches("*.orders"));
+        assert!(!insert("crm.customers").namespace_matches("*.orders"));
+        assert!(!noop().namespace_matches("*"));
+        assert!(!noop().namespace_matches("*.*"));
+    }
+
+    #[test]
+    fn validate_accepts_sound_operations() {
+        for operation in &[noop(), insert(), update(), delete(), command(), apply_ops()] {
+            assert!(operation.validate().is_ok(), "{}", operation);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_updates_without_queries() {
+        let update = |o2: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(1479561394),
+                "op" : "u",
+                "ns" : "foo.bar",
+                "o2" : o2,
+                "o" : { "$set" : { "foo" : "baz" } }
+            })
+        };
+
+        assert!(matches!(
+            update(doc! {}).unwrap().validate(),
+            Err(Error::Validation(_))
+        ));
+        assert!(Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "u",
+            "ns" : "foo.bar",
+            "o" : { "$set" : { "foo" : "baz" } }
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn validate_rejects_malformed_namespaces() {
+        let insert = |ns: &str| {
+            Operation::new(
+                &doc! { "ts" : ts(1479561394), "op" : "i", "ns" : ns, "o" : { "_id" : 1 } },
+            )
+            .unwrap()
+            .validate()
+            .map_err(|e| e.to_string())
         };
-        let operation = Operation::new(&doc).unwrap();
 
         assert_eq!(
-            operation,
-            Operation::Noop {
-                uid: "2135725856567446411i64".to_string(),
-                timestamp: Utc.timestamp(1479419535, 0),
-                message: Some("initiating set".into()),
-            }
+            insert("foo"),
+            Err(
+                "invalid operation: \"foo\" is not a namespace of the form database.collection"
+                    .into()
+            )
         );
+        assert!(insert(".bar").is_err());
+        assert!(insert("fo o.bar").is_err());
+        assert!(insert("foo.").is_err());
+        assert!(insert("foo.$cmd").is_err());
+        assert!(insert("foo.bar.baz").is_ok());
     }
 
     #[test]
-    fn operation_converts_inserts() {
-        let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1479561394 ,
-                increment:0
-            }),
-            "h" : (-1742072865587022793i64),
-            "v" : 2,
-            "op" : "i",
+    fn validate_rejects_deletes_without_ids() {
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "d",
             "ns" : "foo.bar",
-            "o" : {
-                "foo" : "bar"
-            }
+            "o" : { "foo" : "bar" }
+        })
+        .unwrap();
+
+        assert_eq!(
+            operation.validate().unwrap_err().to_string(),
+            "invalid operation: delete query has no _id"
+        );
+    }
+
+    #[test]
+    fn content_eq_ignores_volatile_metadata() {
+        let insert = |time: u32, h: i64, o: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(time),
+                "h" : h,
+                "lsid" : { "uid" : Bson::Binary(bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: vec![h as u8; 3],
+                }) },
+                "op" : "i",
+                "ns" : "foo.bar",
+                "o" : o
+            })
+            .unwrap()
         };
-        let operation = Operation::new(&doc).unwrap();
 
+        let operation = insert(1479561394, 1, doc! { "_id" : 1, "foo" : "bar" });
+
+        let later = insert(1479561395, 2, doc! { "_id" : 1, "foo" : "bar" });
+
+        assert_ne!(operation.uid(), later.uid());
+        assert_ne!(operation, later);
+        assert!(operation.content_eq(&later));
+        assert!(!operation.content_eq(&insert(1479561394, 1, doc! { "_id" : 2, "foo" : "bar" })));
+        assert!(!operation.content_eq(&delete()));
+        assert!(apply_ops().content_eq(&apply_ops()));
+        assert!(!apply_ops().content_eq(&insert(1479561394, 1, doc! { "_id" : 1, "foo" : "bar" })));
+    }
+
+    #[test]
+    fn kind_returns_the_kind_of_each_operation() {
+        let abort = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "o" : { "abortTransaction" : 1 }
+        })
+        .unwrap();
+        let unknown = Operation::with_options(
+            &doc! { "ts" : ts(1479561394), "op" : "x", "ns" : "foo.bar" },
+            &ParseOptions::default().tolerate_unknown(true),
+        )
+        .unwrap();
+
+        assert_eq!(noop().kind(), OperationKind::Noop);
+        assert_eq!(insert().kind(), OperationKind::Insert);
+        assert_eq!(update().kind(), OperationKind::Update);
+        assert_eq!(delete().kind(), OperationKind::Delete);
+        assert_eq!(command().kind(), OperationKind::Command);
+        assert_eq!(apply_ops().kind(), OperationKind::ApplyOps);
+        assert_eq!(abort.kind(), OperationKind::AbortTransaction);
+        assert_eq!(unknown.kind(), OperationKind::Unknown);
+
+        let mut counts = std::collections::HashMap::new();
+        for operation in &[insert(), insert(), delete()] {
+            *counts.entry(operation.kind()).or_insert(0) += 1;
+        }
+        assert_eq!(counts[&OperationKind::Insert], 2);
+    }
+
+    #[test]
+    fn to_tombstone_keys_deletes_without_a_value() {
+        assert_eq!(delete().to_tombstone(), Some((Bson::Int32(1), None)));
+    }
+
+    #[test]
+    fn to_tombstone_keys_inserts_and_updates_with_a_value() {
         assert_eq!(
-            operation,
-            Operation::Insert {
-                uid: "1742072865587022793i64".to_string(),
-                timestamp: Utc.timestamp(1479561394, 0),
-                namespace: "foo.bar".into(),
-                document: doc! { "foo" : "bar" },
-            }
+            insert().to_tombstone(),
+            Some((Bson::Int32(1), Some(doc! { "_id" : 1, "foo" : "bar" })))
+        );
+        assert_eq!(
+            update().to_tombstone(),
+            Some((Bson::Int32(1), Some(doc! { "_id" : 1, "foo" : "baz" })))
         );
+        assert_eq!(command().to_tombstone(), None);
     }
 
     #[test]
-    fn operation_converts_updates() {
-        let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1479561033 ,
-                increment: 0,
-            }),
-            "h" : (3511341713062188019i64),
-            "v" : 2,
-            "op" : "u",
+    fn affects_id_matches_inserts_updates_and_deletes() {
+        for operation in &[insert(), update(), delete()] {
+            assert!(operation.affects_id(&Bson::Int32(1)));
+            assert!(!operation.affects_id(&Bson::Int32(2)));
+        }
+    }
+
+    #[test]
+    fn affects_id_recurses_into_apply_ops() {
+        assert!(apply_ops().affects_id(&Bson::Int32(1)));
+        assert!(!apply_ops().affects_id(&Bson::Int32(2)));
+    }
+
+    #[test]
+    fn affects_id_is_false_for_other_operations() {
+        assert!(!noop().affects_id(&Bson::Int32(1)));
+        assert!(!command().affects_id(&Bson::Int32(1)));
+    }
+
+    #[test]
+    fn document_id_time_decodes_object_ids() {
+        let id = bson::oid::ObjectId::parse_str("507f1f77bcf86cd799439011").unwrap();
+        let operation = Operation::new(&doc! {
+            "ts" : ts(1479561394),
+            "op" : "d",
             "ns" : "foo.bar",
-            "o2" : {
-                "_id" : 1
-            },
-            "o" : {
-                "$set" : {
-                    "foo" : "baz"
-                }
-            }
-        };
-        let operation = Operation::new(&doc).unwrap();
+            "o" : { "_id" : id }
+        })
+        .unwrap();
 
         assert_eq!(
-            operation,
-            Operation::Update {
-                uid: "3511341713062188019i64".to_string(),
-                timestamp: Utc.timestamp(1479561033, 0),
-                namespace: "foo.bar".into(),
-                query: doc! { "_id" : 1 },
-                update: doc! { "$set" : { "foo" : "baz" } },
-            }
+            operation.document_id_time(),
+            Some(Utc.timestamp_opt(1350508407, 0).unwrap())
         );
+        assert_eq!(insert().document_id_time(), None);
+        assert_eq!(noop().document_id_time(), None);
     }
 
     #[test]
-    fn operation_converts_deletes() {
+    fn hash64_returns_the_hash_of_the_entry() {
         let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1479421186 ,
-                increment: 0,
-            }),
-            "h" : (-5457382347563537847i64),
-            "v" : 2,
-            "op" : "d",
+            "ts" : ts(1479561394),
+            "h" : (-1742072865587022793i64),
+            "op" : "i",
             "ns" : "foo.bar",
-            "o" : {
-                "_id" : 1
-            }
+            "o" : { "_id" : 1, "foo" : "bar" }
         };
-        let operation = Operation::new(&doc).unwrap();
 
         assert_eq!(
-            operation,
-            Operation::Delete {
-                uid: "5457382347563537847i64".to_string(),
-                timestamp: Utc.timestamp(1479421186, 0),
-                namespace: "foo.bar".into(),
-                query: doc! { "_id" : 1 },
-            }
+            Operation::new(&doc).unwrap().hash64(),
+            Some(-1742072865587022793)
         );
+        assert_eq!(insert().hash64(), None);
     }
 
     #[test]
-    fn operation_converts_commands() {
+    fn parsed_operation_keeps_the_raw_document() {
         let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1479553955 ,
-                increment: 0,
-            }),
-            "h" : (-7222343681970774929i64),
-            "v" : 2,
-            "op" : "c",
-            "ns" : "test.$cmd",
-            "o" : {
-                "create" : "foo"
-            }
+            "ts" : ts(1479561394),
+            "t" : 2i64,
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1, "foo" : "bar" }
+        };
+        let parsed = ParsedOperation::new(doc).unwrap();
+
+        assert_eq!(parsed.raw().get_i64("t"), Ok(2));
+        assert_eq!(parsed.operation(), &insert());
+        assert_eq!(parsed.into_operation(), insert());
+    }
+
+    #[test]
+    fn operation_converts_wall_clock_times() {
+        let doc = doc! {
+            "ts" : ts(1479561394),
+            "wall" : bson::DateTime::from_millis(1479561396500),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
         };
         let operation = Operation::new(&doc).unwrap();
 
         assert_eq!(
-            operation,
-            Operation::Command {
-                uid: "7222343681970774929i6".to_string(),
-                timestamp: Utc.timestamp(1479553955, 0),
-                namespace: "test.$cmd".into(),
-                command: doc! { "create" : "foo" },
-            }
+            operation.timestamp(),
+            Utc.timestamp_opt(1479561394, 0).unwrap()
+        );
+        assert_eq!(
+            operation.metadata().wall_clock,
+            Some(Utc.timestamp_millis_opt(1479561396500).unwrap())
         );
     }
 
     #[test]
-    fn operation_returns_unknown_operations() {
-        let doc = doc! { "op" : "x" };
-        let operation = Operation::new(&doc);
+    fn operation_parses_collection_uuids() {
+        let uuid = bson::Uuid::parse_str("8aa4bcdb-7d1a-4b3e-9d5a-3b1fbf6f1c4e").unwrap();
+        let doc = doc! {
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "ui" : uuid,
+            "o" : { "_id" : 1 }
+        };
+        let operation = Operation::new(&doc).unwrap();
 
+        assert_eq!(operation.collection_uuid(), Some(uuid));
         match operation {
-            Err(Error::UnknownOperation(op)) => assert_eq!(op, "x"),
-            _ => panic!("Expected unknown operation."),
+            Operation::Insert {
+                collection_uuid, ..
+            } => assert_eq!(collection_uuid, Some(uuid)),
+            operation => panic!("Expected insert, got {}", operation),
         }
     }
 
     #[test]
-    fn operation_returns_missing_fields() {
-        use bson::document::ValueAccessError;
-
-        let doc = doc! { "foo" : "bar" };
-        let operation = Operation::new(&doc);
+    fn operation_without_collection_uuid() {
+        let doc = doc! {
+            "ts" : ts(1479561394),
+            "op" : "d",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        };
 
-        match operation {
-            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::NotPresent),
-            _ => panic!("Expected missing field."),
-        }
+        assert_eq!(Operation::new(&doc).unwrap().collection_uuid(), None);
     }
 
     #[test]
-    fn operation_returns_apply_ops() {
+    fn operation_rejects_invalid_collection_uuids() {
         let doc = doc! {
-            "ts" : Bson::Timestamp(bson::Timestamp {
-                time: 1483789052 ,
-                increment: 0,
-            }),
-            "h" : (-3262249347345468996i64),
-            "v" : 2,
-            "op" : "c",
-            "ns" : "foo.$cmd",
-            "o" : {
-                "applyOps" : [
-                    {
-                        "ts" : Bson::Timestamp(bson::Timestamp {
-                            time: 1479561394 ,
-                            increment: 0,
-                        }),
-                        "t" : 2,
-                        "h" : (-1742072865587022793i64),
-                        "op" : "i",
-                        "ns" : "foo.bar",
-                        "o" : {
-                            "_id" : 1,
-                            "foo" : "bar"
-                        }
-                    }
-                ]
-            }
+            "ts" : ts(1479561394),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "ui" : "not a uuid",
+            "o" : { "_id" : 1 }
         };
-        let operation = Operation::new(&doc).unwrap();
 
-        assert_eq!(
-            operation,
-            Operation::ApplyOps {
-                uid: "3262249347345468996i64".to_string(),
-                timestamp: Utc.timestamp(1483789052, 0),
-                namespace: "foo.$cmd".into(),
-                operations: vec![Operation::Insert {
-                    uid: "1742072865587022793i64".to_string(),
-                    timestamp: Utc.timestamp(1479561394, 0),
-                    namespace: "foo.bar".into(),
-                    document: doc! { "_id" : 1, "foo" : "bar" },
-                }],
-            }
-        );
+        assert!(matches!(
+            Operation::new(&doc),
+            Err(Error::MissingField(ValueAccessError::UnexpectedType))
+        ));
     }
 }
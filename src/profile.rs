@@ -0,0 +1,146 @@
+//! The profile module is responsible for aggregating operations by namespace to profile the oplog,
+//! e.g. to find which collections are written to the most.
+
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+use crate::{Operation, OperationKind};
+
+/// Counts operations per namespace and kind of operation.
+///
+/// Operations contained in `ApplyOps` are counted individually under their own namespaces, so
+/// the writes of transactions are attributed to the collections they changed. No-ops have no
+/// namespace and are counted under the empty namespace `""`.
+///
+/// # Example
+///
+/// ```
+/// use oplog::bson::{self, doc, Bson};
+/// use oplog::{NamespaceCounter, Operation};
+///
+/// let document = doc! {
+///     "ts": Bson::Timestamp(bson::Timestamp {
+///         time: 1479561394,
+///         increment: 0,
+///     }),
+///     "op": "i",
+///     "ns": "foo.bar",
+///     "o": { "_id": 1, "foo": "bar" }
+/// };
+/// let operations = vec![Operation::new(&document)?, Operation::new(&document)?];
+///
+/// let counter: NamespaceCounter = operations.into_iter().collect();
+///
+/// assert_eq!(counter.counts()["foo.bar"], 2);
+/// # Ok::<(), oplog::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct NamespaceCounter {
+    /// The number of operations of each kind counted in each namespace.
+    counts: HashMap<String, HashMap<OperationKind, usize>>,
+}
+
+impl NamespaceCounter {
+    /// Returns a counter with no operations counted.
+    pub fn new() -> NamespaceCounter {
+        NamespaceCounter::default()
+    }
+
+    /// Count an operation, including each operation contained in `ApplyOps`.
+    pub fn push(&mut self, operation: &Operation) {
+        for operation in operation.flatten() {
+            let namespace = operation.namespace().unwrap_or_default();
+
+            *self
+                .counts
+                .entry(namespace.to_string())
+                .or_default()
+                .entry(operation.kind())
+                .or_default() += 1;
+        }
+    }
+
+    /// Returns the number of operations counted in each namespace.
+    pub fn counts(&self) -> HashMap<String, usize> {
+        self.counts
+            .iter()
+            .map(|(namespace, kinds)| (namespace.clone(), kinds.values().sum()))
+            .collect()
+    }
+
+    /// Returns the number of operations of each kind counted in each namespace.
+    pub fn counts_by_kind(&self) -> &HashMap<String, HashMap<OperationKind, usize>> {
+        &self.counts
+    }
+}
+
+impl Extend<Operation> for NamespaceCounter {
+    fn extend<I: IntoIterator<Item = Operation>>(&mut self, operations: I) {
+        for operation in operations {
+            self.push(&operation);
+        }
+    }
+}
+
+impl FromIterator<Operation> for NamespaceCounter {
+    fn from_iter<I: IntoIterator<Item = Operation>>(operations: I) -> NamespaceCounter {
+        let mut counter = NamespaceCounter::new();
+        counter.extend(operations);
+
+        counter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson, Document};
+    use mongodb::bson;
+
+    fn operation(op: &str, ns: &str, o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment: 0 }),
+            "op" : op,
+            "ns" : ns,
+            "o2" : { "_id" : 1 },
+            "o" : o
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn namespace_counter_counts_a_mixed_stream() {
+        let operations = vec![
+            operation("n", "", doc! { "msg" : "periodic noop" }),
+            operation("i", "foo.bar", doc! { "_id" : 1 }),
+            operation("u", "foo.bar", doc! { "$set" : { "a" : 1 } }),
+            operation("i", "foo.bar", doc! { "_id" : 2 }),
+            operation("d", "foo.baz", doc! { "_id" : 1 }),
+            operation(
+                "c",
+                "admin.$cmd",
+                doc! {
+                    "applyOps" : [
+                        { "op" : "i", "ns" : "foo.baz", "o" : { "_id" : 2 } },
+                        { "op" : "i", "ns" : "foo.qux", "o" : { "_id" : 1 } }
+                    ]
+                },
+            ),
+        ];
+
+        let counter: NamespaceCounter = operations.into_iter().collect();
+        let counts = counter.counts();
+
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts[""], 1);
+        assert_eq!(counts["foo.bar"], 3);
+        assert_eq!(counts["foo.baz"], 2);
+        assert_eq!(counts["foo.qux"], 1);
+
+        let foo_bar = &counter.counts_by_kind()["foo.bar"];
+        assert_eq!(foo_bar[&OperationKind::Insert], 2);
+        assert_eq!(foo_bar[&OperationKind::Update], 1);
+        assert_eq!(foo_bar.get(&OperationKind::Delete), None);
+        assert_eq!(counter.counts_by_kind()[""][&OperationKind::Noop], 1);
+    }
+}
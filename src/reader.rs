@@ -0,0 +1,167 @@
+//! The reader module is responsible for parsing operations from files of BSON oplog entries (e.g.
+//! `mongodump` output of `local.oplog.rs`) without a live server.
+
+use std::io::{self, Read};
+
+use crate::{Error, Operation, Result};
+
+/// An iterator over the operations in a stream of concatenated BSON documents.
+///
+/// Each document is read in turn by its length prefix and converted with `Operation::new`.
+/// Iteration ends cleanly at the end of the stream but a document truncated part of the way
+/// through (e.g. by an interrupted dump) returns `Error::Deserialize` and ends iteration, as do
+/// any I/O errors. Documents which are valid BSON but not valid operations return their error
+/// without ending iteration.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use oplog::OplogFileReader;
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = File::open("dump/local/oplog.rs.bson")?;
+///
+/// for res in OplogFileReader::new(BufReader::new(file)) {
+///     println!("{}", res?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct OplogFileReader<R> {
+    /// The underlying reader of BSON documents.
+    reader: R,
+    /// Whether the end of the stream or an unrecoverable error has been reached.
+    done: bool,
+}
+
+impl<R: Read> OplogFileReader<R> {
+    /// Returns a reader of the operations in the given stream of BSON documents.
+    pub fn new(reader: R) -> OplogFileReader<R> {
+        OplogFileReader {
+            reader,
+            done: false,
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the raw bytes of the next document, returning whether it was read in full.
+    fn read_document(&mut self, bytes: &mut Vec<u8>) -> io::Result<bool> {
+        let mut prefix = [0; 4];
+        let mut read = 0;
+
+        while read < prefix.len() {
+            match self.reader.read(&mut prefix[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        bytes.extend_from_slice(&prefix[..read]);
+
+        if read < prefix.len() {
+            return Ok(false);
+        }
+
+        let len = i32::from_le_bytes(prefix);
+        if len < 5 {
+            return Ok(false);
+        }
+
+        let rest = len as u64 - prefix.len() as u64;
+        let read = (&mut self.reader).take(rest).read_to_end(bytes)?;
+
+        Ok(read as u64 == rest)
+    }
+}
+
+impl<R: Read> Iterator for OplogFileReader<R> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut bytes = Vec::new();
+
+        match self.read_document(&mut bytes) {
+            Ok(true) => Some(Operation::from_slice(&bytes)),
+            Ok(false) if bytes.is_empty() => {
+                self.done = true;
+                None
+            }
+            Ok(false) => {
+                self.done = true;
+                Some(Operation::from_slice(&bytes))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(Error::Io(e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson, Document};
+    use mongodb::bson;
+
+    fn entry(increment: u32) -> Document {
+        doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1479561394, increment }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : increment }
+        }
+    }
+
+    fn dump(documents: &[Document]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for document in documents {
+            document.to_writer(&mut bytes).unwrap();
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn oplog_file_reader_reads_every_operation() {
+        let bytes = dump(&[entry(1), entry(2)]);
+        let operations: Vec<Operation> = OplogFileReader::new(&bytes[..])
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            operations,
+            vec![
+                Operation::new(&entry(1)).unwrap(),
+                Operation::new(&entry(2)).unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn oplog_file_reader_ends_at_truncated_documents() {
+        let bytes = dump(&[entry(1), entry(2)]);
+        let mut reader = OplogFileReader::new(&bytes[..bytes.len() - 3]);
+
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::Deserialize(_)))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn oplog_file_reader_is_empty_without_documents() {
+        assert!(OplogFileReader::new(&b""[..]).next().is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! The record batch module is responsible for converting operations into Apache Arrow record
+//! batches, e.g. to load oplog data into columnar stores for analytics.
+
+use std::sync::Arc;
+
+use ::arrow::array::{ArrayRef, StringArray, UInt64Array};
+use ::arrow::datatypes::{DataType, Field, Schema};
+use ::arrow::record_batch::RecordBatch;
+use bson::Bson;
+use mongodb::bson;
+
+use crate::Operation;
+
+/// Convert operations into an Arrow record batch with one row per operation.
+///
+/// The batch has the following columns:
+///
+/// * `op`: the oplog op code (e.g. `"i"`), non-nullable UTF-8;
+/// * `ns`: the namespace, nullable UTF-8 (no-ops have none);
+/// * `ts`: the oplog timestamp as a non-nullable `UInt64` of its time in the upper 32 bits and its
+///   increment in the lower 32 bits, as MongoDB stores it, so it sorts in oplog order;
+/// * `uid`: the session of the operation, nullable UTF-8;
+/// * `payload`: the documents carried by the operation (see `Operation::documents`) as a JSON
+///   array of relaxed Extended JSON, non-nullable UTF-8.
+///
+/// Arrow has no equivalent of arbitrary BSON so nested documents are flattened to the JSON string
+/// of the `payload` column rather than mapped to nested Arrow types. The operations contained in
+/// `ApplyOps` are not given rows of their own but their documents are included in its payload;
+/// use `Operation::flatten` first to give each its own row. This requires the `arrow` feature.
+///
+/// # Example
+///
+/// ```
+/// use oplog::bson::{self, doc, Bson};
+/// use oplog::Operation;
+///
+/// let document = doc! {
+///     "ts": Bson::Timestamp(bson::Timestamp {
+///         time: 1479561394,
+///         increment: 0,
+///     }),
+///     "op": "i",
+///     "ns": "foo.bar",
+///     "o": { "_id": 1, "foo": "bar" }
+/// };
+/// let batch = oplog::operations_to_record_batch(&[Operation::new(&document)?]);
+///
+/// assert_eq!(batch.num_rows(), 1);
+/// # Ok::<(), oplog::Error>(())
+/// ```
+pub fn operations_to_record_batch(operations: &[Operation]) -> RecordBatch {
+    let op: StringArray = operations.iter().map(|o| Some(o.op_char())).collect();
+    let ns: StringArray = operations.iter().map(Operation::namespace).collect();
+    let ts: UInt64Array = operations
+        .iter()
+        .map(|o| {
+            let optime = o.oplog_ts();
+            Some(u64::from(optime.time) << 32 | u64::from(optime.increment))
+        })
+        .collect();
+    let uid: StringArray = operations.iter().map(Operation::uid).collect();
+    let payload: StringArray = operations.iter().map(|o| Some(payload(o))).collect();
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(op),
+        Arc::new(ns),
+        Arc::new(ts),
+        Arc::new(uid),
+        Arc::new(payload),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema()), columns)
+        .expect("columns should match the schema of operations")
+}
+
+/// Returns the Arrow schema of record batches of operations.
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("op", DataType::Utf8, false),
+        Field::new("ns", DataType::Utf8, true),
+        Field::new("ts", DataType::UInt64, false),
+        Field::new("uid", DataType::Utf8, true),
+        Field::new("payload", DataType::Utf8, false),
+    ])
+}
+
+/// Returns the documents of an operation as a JSON array of relaxed Extended JSON.
+fn payload(operation: &Operation) -> String {
+    let documents = operation
+        .documents()
+        .into_iter()
+        .map(|document| Bson::Document(document.clone()))
+        .collect();
+
+    Bson::Array(documents).into_relaxed_extjson().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn ts() -> Bson {
+        Bson::Timestamp(bson::Timestamp {
+            time: 1479561394,
+            increment: 1,
+        })
+    }
+
+    #[test]
+    fn operations_to_record_batch_has_a_row_per_operation() {
+        let operations = vec![
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "i",
+                "ns" : "foo.bar",
+                "o" : { "_id" : 1, "foo" : "bar" }
+            })
+            .unwrap(),
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "d",
+                "ns" : "foo.bar",
+                "o" : { "_id" : 1 }
+            })
+            .unwrap(),
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "n",
+                "ns" : "",
+                "o" : { "msg" : "initiating set" }
+            })
+            .unwrap(),
+        ];
+
+        let batch = operations_to_record_batch(&operations);
+
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(*batch.schema(), schema());
+
+        let op = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(op.value(1), "d");
+
+        let ts = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(ts.value(0), 1479561394 << 32 | 1);
+
+        let payload = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(payload.value(0), r#"[{"_id":1,"foo":"bar"}]"#);
+    }
+}
@@ -0,0 +1,336 @@
+//! The redact module is responsible for masking sensitive fields in the documents carried by
+//! operations so they can be logged without leaking their values.
+
+use bson::{Bson, Document};
+use mongodb::bson;
+
+use crate::{Operation, UpdateModification};
+
+/// The value replacing every redacted field.
+const MARKER: &str = "***";
+
+/// Returns a copy of an operation with the given dotted field paths redacted from its documents.
+pub(crate) fn redact(operation: &Operation, fields: &[&str]) -> Operation {
+    let mut operation = operation.clone();
+    redact_operation(&mut operation, fields);
+
+    operation
+}
+
+/// Redact the given field paths from the documents of an operation in place.
+fn redact_operation(operation: &mut Operation, fields: &[&str]) {
+    match *operation {
+        Operation::Insert {
+            ref mut document,
+            ref mut query,
+            ..
+        } => {
+            redact_document(document, fields);
+            if let Some(ref mut query) = *query {
+                redact_document(query, fields);
+            }
+        }
+        Operation::Update {
+            ref mut query,
+            ref mut update,
+            ref mut modification,
+            ref mut pre_image,
+            ..
+        } => {
+            redact_document(query, fields);
+            redact_update(update, fields);
+            redact_modification(modification, fields);
+            if let Some(ref mut pre_image) = *pre_image {
+                redact_document(pre_image, fields);
+            }
+        }
+        Operation::Delete {
+            ref mut query,
+            ref mut pre_image,
+            ..
+        } => {
+            redact_document(query, fields);
+            if let Some(ref mut pre_image) = *pre_image {
+                redact_document(pre_image, fields);
+            }
+        }
+        Operation::Command {
+            ref mut command, ..
+        } => redact_document(command, fields),
+        Operation::ApplyOps {
+            ref mut operations, ..
+        } => {
+            for operation in operations {
+                redact_operation(operation, fields);
+            }
+        }
+        Operation::Unknown {
+            ref mut document, ..
+        } => {
+            for key in &["o", "o2"] {
+                if let Some(&mut Bson::Document(ref mut payload)) = document.get_mut(key) {
+                    redact_document(payload, fields);
+                }
+            }
+        }
+        Operation::Noop { .. }
+        | Operation::CommitTransaction { .. }
+        | Operation::AbortTransaction { .. } => {}
+    }
+}
+
+/// Redact the given field paths from the raw `o` field of an update oplog entry.
+///
+/// Operators and `$v: 2` diffs nest the changed fields beneath them so paths are redacted within
+/// each operator or diff section rather than from the top level.
+fn redact_update(update: &mut Document, fields: &[&str]) {
+    if let Ok(2) = update.get_i32("$v") {
+        if let Ok(diff) = update.get_document_mut("diff") {
+            for field in fields {
+                redact_diff(diff, field);
+            }
+        }
+    } else if update.keys().any(|key| key.starts_with('$')) {
+        redact_operators(update, fields);
+    } else {
+        redact_document(update, fields);
+    }
+}
+
+/// Redact the given field paths from the structured interpretation of an update.
+fn redact_modification(modification: &mut UpdateModification, fields: &[&str]) {
+    match *modification {
+        UpdateModification::Replacement(ref mut document) => redact_document(document, fields),
+        UpdateModification::Operators(ref mut operators) => redact_operators(operators, fields),
+        UpdateModification::Diff {
+            ref mut set,
+            ref mut insert,
+            ..
+        } => {
+            redact_document(set, fields);
+            redact_document(insert, fields);
+        }
+    }
+}
+
+/// Redact the given field paths from the arguments of every update operator.
+fn redact_operators(operators: &mut Document, fields: &[&str]) {
+    for (_, arguments) in operators.iter_mut() {
+        if let Bson::Document(ref mut arguments) = *arguments {
+            redact_document(arguments, fields);
+        }
+    }
+}
+
+/// Redact the given field paths from a document.
+fn redact_document(document: &mut Document, fields: &[&str]) {
+    for field in fields {
+        redact_path(document, field);
+    }
+}
+
+/// Redact a dotted field path from a document.
+///
+/// Keys which are themselves dotted paths (e.g. the arguments of `$set`) are redacted if they
+/// fall under the path, and an empty path redacts every field.
+fn redact_path(document: &mut Document, path: &str) {
+    for (key, value) in document.iter_mut() {
+        if covers(key, path) {
+            *value = Bson::String(MARKER.into());
+        } else if let Some(rest) = beneath(key, path) {
+            redact_value(value, rest);
+        }
+    }
+}
+
+/// Redact a dotted field path from within an embedded document or array.
+///
+/// Arrays are addressed either by element index (e.g. `items.0.price`) or, as in MongoDB
+/// queries, by the field of every element (e.g. `items.price`).
+fn redact_value(value: &mut Bson, path: &str) {
+    match *value {
+        Bson::Document(ref mut document) => redact_path(document, path),
+        Bson::Array(ref mut values) => {
+            let (head, rest) = match path.split_once('.') {
+                Some((head, rest)) => (head, Some(rest)),
+                None => (path, None),
+            };
+
+            match (head.parse::<usize>(), rest) {
+                (Ok(index), None) => {
+                    if let Some(value) = values.get_mut(index) {
+                        *value = Bson::String(MARKER.into());
+                    }
+                }
+                (Ok(index), Some(rest)) => {
+                    if let Some(value) = values.get_mut(index) {
+                        redact_value(value, rest);
+                    }
+                }
+                (Err(_), _) => {
+                    for value in values {
+                        redact_value(value, path);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Redact a dotted field path from a (possibly nested) `$v: 2` diff.
+///
+/// See `update::parse_diff` for the layout of diffs.
+fn redact_diff(diff: &mut Document, path: &str) {
+    for (key, value) in diff.iter_mut() {
+        if !key.is_char_boundary(1) {
+            continue;
+        }
+        let (kind, field) = key.split_at(1);
+
+        match (kind, value) {
+            ("u", &mut Bson::Document(ref mut fields))
+            | ("i", &mut Bson::Document(ref mut fields))
+                if field.is_empty() =>
+            {
+                redact_path(fields, path)
+            }
+            ("u", value) if covers(field, path) => *value = Bson::String(MARKER.into()),
+            ("s", &mut Bson::Document(ref mut sub_diff)) => {
+                if covers(field, path) {
+                    redact_diff(sub_diff, "");
+                } else if let Some(rest) = beneath(field, path) {
+                    redact_diff(sub_diff, rest);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns whether a key is redacted by a path, i.e. is the same path or a path beneath it.
+fn covers(key: &str, path: &str) -> bool {
+    path.is_empty()
+        || key
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Returns the rest of a path beneath a key, if the path is within it.
+fn beneath<'a>(key: &str, path: &'a str) -> Option<&'a str> {
+    path.strip_prefix(key)?.strip_prefix('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn ts() -> Bson {
+        Bson::Timestamp(bson::Timestamp {
+            time: 1479561394,
+            increment: 0,
+        })
+    }
+
+    fn insert(document: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : ts(),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : document
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn redact_replaces_top_level_fields() {
+        let operation = insert(doc! { "_id" : 1, "email" : "a@example.com", "name" : "A" });
+
+        assert_eq!(
+            operation.redact(&["email"]),
+            insert(doc! { "_id" : 1, "email" : "***", "name" : "A" })
+        );
+    }
+
+    #[test]
+    fn redact_replaces_nested_fields() {
+        let operation = insert(doc! {
+            "_id" : 1,
+            "card" : { "number" : "4111111111111111", "brand" : "visa" },
+            "contacts" : [{ "phone" : "0123" }, { "phone" : "4567" }]
+        });
+
+        assert_eq!(
+            operation.redact(&["card.number", "contacts.phone", "missing.field"]),
+            insert(doc! {
+                "_id" : 1,
+                "card" : { "number" : "***", "brand" : "visa" },
+                "contacts" : [{ "phone" : "***" }, { "phone" : "***" }]
+            })
+        );
+    }
+
+    #[test]
+    fn redact_replaces_fields_of_upsert_queries() {
+        let upsert = |email: &str| {
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "i",
+                "ns" : "foo.bar",
+                "o2" : { "_id" : 1, "email" : email },
+                "o" : { "_id" : 1, "email" : email }
+            })
+            .unwrap()
+        };
+
+        assert_eq!(upsert("a@example.com").redact(&["email"]), upsert("***"));
+    }
+
+    #[test]
+    fn redact_replaces_fields_set_by_updates() {
+        let update = |o: Document| {
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "u",
+                "ns" : "foo.bar",
+                "o2" : { "_id" : 1 },
+                "o" : o
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            update(doc! { "$set" : { "card.number" : "4111", "card.brand" : "visa" } })
+                .redact(&["card.number"]),
+            update(doc! { "$set" : { "card.number" : "***", "card.brand" : "visa" } })
+        );
+        assert_eq!(
+            update(doc! { "$v" : 2, "diff" : { "scard" : { "u" : { "number" : "4111" } } } })
+                .redact(&["card"]),
+            update(doc! { "$v" : 2, "diff" : { "scard" : { "u" : { "number" : "***" } } } })
+        );
+    }
+
+    #[test]
+    fn redact_recurses_into_apply_ops() {
+        let apply_ops = |email: &str| {
+            Operation::new(&doc! {
+                "ts" : ts(),
+                "op" : "c",
+                "ns" : "admin.$cmd",
+                "o" : {
+                    "applyOps" : [
+                        { "op" : "i", "ns" : "foo.bar", "o" : { "_id" : 1, "email" : email } }
+                    ]
+                }
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            apply_ops("a@example.com").redact(&["email"]),
+            apply_ops("***")
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! The retry module is responsible for the policy governing how an `Oplog` re-establishes its
+//! cursor after a connection or cursor error.
+
+use rand::Rng;
+use std::time::Duration;
+
+/// A policy for reconnecting to the oplog with exponential backoff and jitter.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use mongodb::Client;
+/// use oplog::{Oplog, RetryPolicy};
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+///
+/// let mut oplog = Oplog::builder()
+///     .reconnect(
+///         RetryPolicy::default()
+///             .max_retries(10)
+///             .initial_delay(Duration::from_millis(50)),
+///     )
+///     .build(&client)
+///     .await?;
+///
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Set the maximum number of consecutive reconnection attempts.
+    ///
+    /// This is 5 by default. The count is reset whenever an operation is read successfully.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the delay before the first reconnection attempt.
+    ///
+    /// This is 100 milliseconds by default and doubles with each subsequent attempt.
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// Set the maximum delay between reconnection attempts.
+    ///
+    /// This is 10 seconds by default.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Returns the delay before the given (zero-indexed) attempt or `None` if there are no more
+    /// attempts left.
+    ///
+    /// The delay is chosen at random between half and all of the exponential backoff so that many
+    /// tailers don't reconnect in lockstep.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+
+        let backoff = self
+            .initial_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+
+        Some(backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_exponentially_with_jitter() {
+        let policy = RetryPolicy::default().initial_delay(Duration::from_millis(100));
+
+        for attempt in 0..3 {
+            let backoff = Duration::from_millis(100 * 2u64.pow(attempt));
+            let delay = policy.delay(attempt).unwrap();
+
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = RetryPolicy::default()
+            .max_retries(100)
+            .max_delay(Duration::from_secs(1));
+
+        assert!(policy.delay(99).unwrap() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn delay_runs_out_after_max_retries() {
+        let policy = RetryPolicy::default().max_retries(2);
+
+        assert!(policy.delay(1).is_some());
+        assert_eq!(policy.delay(2), None);
+    }
+}
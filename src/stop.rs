@@ -0,0 +1,79 @@
+//! The stop module is responsible for signalling an `Oplog` to end from elsewhere (e.g. another
+//! task or a shutdown handler) rather than awaiting new operations forever.
+
+use futures::task::AtomicWaker;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Context;
+
+/// A handle which stops an `Oplog` when signalled.
+///
+/// This is returned by `Oplog::stop_handle` and can be cloned and sent to other tasks or threads.
+/// Once `stop` is called, the oplog closes its cursor and ends (i.e. yields `None`) the next time
+/// it is polled, even while waiting for new operations from the server.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::Oplog;
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+///
+/// let mut oplog = Oplog::new(&client).await?;
+/// let handle = oplog.stop_handle();
+///
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     handle.stop();
+/// });
+///
+/// while let Some(res) = oplog.next().await {
+///     println!("{}", res?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle {
+    inner: Arc<Signal>,
+}
+
+/// The state shared between an `Oplog` and its stop handles.
+#[derive(Debug, Default)]
+struct Signal {
+    /// Whether the oplog has been signalled to stop.
+    stopped: AtomicBool,
+    /// Wakes the task polling the oplog when signalled.
+    waker: AtomicWaker,
+}
+
+impl StopHandle {
+    /// Signal the oplog to stop.
+    ///
+    /// Any operations already read from the server but not yet yielded are discarded so callers
+    /// resuming later should do so from the last operation they processed.
+    pub fn stop(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        self.inner.waker.wake();
+    }
+
+    /// Returns whether the oplog has been signalled to stop.
+    pub fn is_stopped(&self) -> bool {
+        self.inner.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the oplog has been signalled to stop, registering the current task to be
+    /// woken when it is if not.
+    pub(crate) fn poll_stopped(&self, cx: &Context<'_>) -> bool {
+        if self.is_stopped() {
+            return true;
+        }
+
+        self.inner.waker.register(cx.waker());
+
+        self.is_stopped()
+    }
+}
@@ -0,0 +1,263 @@
+//! The transaction module is responsible for reassembling multi-document transactions which
+//! MongoDB splits across several `applyOps` oplog entries.
+//!
+//! Large transactions are written as a chain of `applyOps` entries marked with `partialTxn`, ending
+//! in an entry without it. Prepared transactions end in an entry marked with `prepare` and are only
+//! applied once a later `commitTransaction` command arrives (or discarded by `abortTransaction`).
+
+use std::collections::HashMap;
+
+use crate::Operation;
+use mongodb::bson;
+
+/// The session and transaction number identifying a transaction.
+type TransactionKey = (Option<bson::Uuid>, i64);
+
+/// Reassembles transactions split across multiple oplog entries into a single
+/// `Operation::ApplyOps`.
+///
+/// Operations are pushed in oplog order and any operations not belonging to an incomplete
+/// transaction are returned immediately.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use mongodb::Client;
+/// use oplog::{Oplog, TransactionReassembler};
+///
+/// # async fn run() -> Result<(), oplog::Error> {
+/// let client = Client::with_uri_str("mongodb://localhost").await?;
+/// let mut oplog = Oplog::new(&client).await?;
+/// let mut reassembler = TransactionReassembler::new();
+///
+/// while let Some(res) = oplog.next().await {
+///     if let Some(oper) = reassembler.push(res?) {
+///         println!("{:?}", oper);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TransactionReassembler {
+    pending: HashMap<TransactionKey, Vec<Operation>>,
+}
+
+impl TransactionReassembler {
+    /// Creates an empty reassembler.
+    pub fn new() -> TransactionReassembler {
+        TransactionReassembler::default()
+    }
+
+    /// Push the next operation from the oplog, returning an operation if one is complete.
+    ///
+    /// Partial and prepared `applyOps` entries are buffered by session and transaction number
+    /// until the final entry or `commitTransaction` command arrives, at which point a single
+    /// `Operation::ApplyOps` containing all of the transaction's operations is returned. An
+    /// `abortTransaction` command discards any buffered operations.
+    pub fn push(&mut self, operation: Operation) -> Option<Operation> {
+        let key = match transaction_key(&operation) {
+            Some(key) => key,
+            None => return Some(operation),
+        };
+
+        match operation {
+            Operation::ApplyOps {
+                uid,
+                timestamp,
+                optime,
+                metadata,
+                namespace,
+                operations,
+                partial_txn,
+                prepare,
+            } => {
+                let mut buffered = self.pending.remove(&key).unwrap_or_default();
+                buffered.extend(operations);
+
+                if partial_txn || prepare {
+                    self.pending.insert(key, buffered);
+
+                    None
+                } else {
+                    Some(Operation::ApplyOps {
+                        uid,
+                        timestamp,
+                        optime,
+                        metadata,
+                        namespace,
+                        operations: buffered,
+                        partial_txn: false,
+                        prepare: false,
+                    })
+                }
+            }
+            Operation::CommitTransaction {
+                uid,
+                timestamp,
+                optime,
+                metadata,
+                namespace,
+                ..
+            } if self.pending.contains_key(&key) => Some(Operation::ApplyOps {
+                uid,
+                timestamp,
+                optime,
+                metadata,
+                namespace,
+                operations: self.pending.remove(&key).unwrap_or_default(),
+                partial_txn: false,
+                prepare: false,
+            }),
+            Operation::AbortTransaction { .. } if self.pending.remove(&key).is_some() => None,
+            operation => Some(operation),
+        }
+    }
+
+    /// Returns the number of transactions currently awaiting further entries.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Returns the session and transaction number of an operation, if it has one.
+fn transaction_key(operation: &Operation) -> Option<TransactionKey> {
+    let metadata = operation.metadata();
+
+    metadata
+        .txn_number
+        .map(|txn_number| (metadata.session_id, txn_number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::{doc, Bson, Document};
+
+    fn entry(time: u32, session_id: bson::Uuid, o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time, increment: 0 }),
+            "op" : "c",
+            "ns" : "admin.$cmd",
+            "lsid" : { "id" : session_id },
+            "txnNumber" : 1i64,
+            "o" : o
+        })
+        .unwrap()
+    }
+
+    fn insert(id: i32) -> Document {
+        doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1, increment: 0 }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : id }
+        }
+    }
+
+    fn inserted_ids(operation: &Operation) -> Vec<i32> {
+        match *operation {
+            Operation::ApplyOps { ref operations, .. } => operations
+                .iter()
+                .map(|operation| match *operation {
+                    Operation::Insert { ref document, .. } => document.get_i32("_id").unwrap(),
+                    _ => panic!("Expected insert."),
+                })
+                .collect(),
+            _ => panic!("Expected apply ops."),
+        }
+    }
+
+    #[test]
+    fn reassembler_combines_chained_partial_entries() {
+        let session_id = bson::Uuid::new();
+        let mut reassembler = TransactionReassembler::new();
+
+        let first = entry(
+            1,
+            session_id,
+            doc! { "applyOps" : [insert(1)], "partialTxn" : true },
+        );
+        let second = entry(
+            2,
+            session_id,
+            doc! { "applyOps" : [insert(2)], "count" : 2 },
+        );
+
+        assert_eq!(reassembler.push(first), None);
+        assert_eq!(reassembler.pending(), 1);
+
+        let operation = reassembler.push(second).unwrap();
+
+        assert_eq!(inserted_ids(&operation), vec![1, 2]);
+        assert_eq!(operation.timestamp().timestamp(), 2);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn reassembler_waits_for_prepared_transactions_to_commit() {
+        let session_id = bson::Uuid::new();
+        let mut reassembler = TransactionReassembler::new();
+
+        let prepare = entry(
+            1,
+            session_id,
+            doc! { "applyOps" : [insert(1)], "prepare" : true },
+        );
+        let commit = entry(2, session_id, doc! { "commitTransaction" : 1 });
+
+        assert_eq!(reassembler.push(prepare), None);
+
+        let operation = reassembler.push(commit).unwrap();
+
+        assert_eq!(inserted_ids(&operation), vec![1]);
+    }
+
+    #[test]
+    fn reassembler_discards_aborted_transactions() {
+        let session_id = bson::Uuid::new();
+        let mut reassembler = TransactionReassembler::new();
+
+        let prepare = entry(
+            1,
+            session_id,
+            doc! { "applyOps" : [insert(1)], "prepare" : true },
+        );
+        let abort = entry(2, session_id, doc! { "abortTransaction" : 1 });
+
+        assert_eq!(reassembler.push(prepare), None);
+        assert_eq!(reassembler.push(abort), None);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn reassembler_keeps_sessions_apart() {
+        let mut reassembler = TransactionReassembler::new();
+
+        let first = entry(
+            1,
+            bson::Uuid::new(),
+            doc! { "applyOps" : [insert(1)], "partialTxn" : true },
+        );
+        let second = entry(2, bson::Uuid::new(), doc! { "applyOps" : [insert(2)] });
+
+        assert_eq!(reassembler.push(first), None);
+        assert_eq!(inserted_ids(&reassembler.push(second).unwrap()), vec![2]);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn reassembler_passes_through_other_operations() {
+        let mut reassembler = TransactionReassembler::new();
+        let operation = Operation::new(&doc! {
+            "ts" : Bson::Timestamp(bson::Timestamp { time: 1, increment: 0 }),
+            "op" : "i",
+            "ns" : "foo.bar",
+            "o" : { "_id" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(reassembler.push(operation.clone()), Some(operation));
+    }
+}
@@ -0,0 +1,523 @@
+//! The update module is responsible for interpreting the modification stored in the `o` field of an
+//! update oplog entry.
+//!
+//! Depending on the server version and the kind of update, this is either a full replacement
+//! document, a document of update operators (e.g. `$set`) or, since MongoDB 5.0, a versioned diff
+//! of the form `{ "$v": 2, "diff": { ... } }`.
+
+use crate::{Error, Result, ServerVersion};
+use bson::{Bson, Document};
+use mongodb::bson;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The modification applied by an update operation.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UpdateModification {
+    /// A replacement of the whole document.
+    Replacement(Document),
+    /// A document of update operators such as `$set` and `$unset`.
+    Operators(Document),
+    /// A MongoDB 5.0+ `$v: 2` diff with nested sub-diffs flattened into dotted field paths.
+    Diff {
+        /// The fields updated by the diff and their new values.
+        set: Document,
+        /// The fields removed by the diff.
+        unset: Vec<String>,
+        /// The fields added by the diff and their values.
+        insert: Document,
+    },
+}
+
+/// A change to a single field of a document made by an update.
+///
+/// Paths are dotted (e.g. `address.city`) with array elements addressed by their index (e.g.
+/// `items.1.qty`), as recorded in the oplog.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FieldChange {
+    /// The field at `path` was set to `value`.
+    Set {
+        /// The dotted path of the field.
+        path: String,
+        /// The new value of the field.
+        value: Bson,
+    },
+    /// The field at `path` was removed.
+    Unset {
+        /// The dotted path of the field.
+        path: String,
+    },
+    /// The field at `from` was renamed to `to`.
+    Rename {
+        /// The original dotted path of the field.
+        from: String,
+        /// The new dotted path of the field.
+        to: String,
+    },
+    /// The field at `path` was changed by any other update operator (e.g. `$inc`).
+    Other {
+        /// The update operator, e.g. `$inc`.
+        operator: String,
+        /// The dotted path of the field.
+        path: String,
+        /// The argument of the operator.
+        value: Bson,
+    },
+}
+
+impl UpdateModification {
+    /// Returns the changes made to individual fields by update operators or a diff.
+    ///
+    /// This gives the same representation for legacy `$set`/`$unset` updates and `$v: 2` diffs.
+    /// Replacements don't change individual fields so return `None`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::bson::{self, doc, Bson};
+    /// use oplog::{FieldChange, Operation};
+    ///
+    /// let document = doc! {
+    ///     "ts": Bson::Timestamp(bson::Timestamp {
+    ///         time: 1479561394,
+    ///         increment: 0,
+    ///     }),
+    ///     "op": "u",
+    ///     "ns": "foo.bar",
+    ///     "o2": { "_id": 1 },
+    ///     "o": { "$set": { "address.city": "Leeds" } }
+    /// };
+    ///
+    /// if let Operation::Update { modification, .. } = Operation::new(&document)? {
+    ///     assert_eq!(
+    ///         modification.field_changes(),
+    ///         Some(vec![FieldChange::Set {
+    ///             path: "address.city".into(),
+    ///             value: "Leeds".into(),
+    ///         }])
+    ///     );
+    /// }
+    /// # Ok::<(), oplog::Error>(())
+    /// ```
+    pub fn field_changes(&self) -> Option<Vec<FieldChange>> {
+        match *self {
+            UpdateModification::Replacement(_) => None,
+            UpdateModification::Operators(ref operators) => {
+                let mut changes = Vec::new();
+
+                for (operator, fields) in operators {
+                    let fields = match fields.as_document() {
+                        Some(fields) => fields,
+                        // The `$v` version marker is not an operator.
+                        None => continue,
+                    };
+
+                    for (path, value) in fields {
+                        changes.push(match operator.as_str() {
+                            "$set" => FieldChange::Set {
+                                path: path.clone(),
+                                value: value.clone(),
+                            },
+                            "$unset" => FieldChange::Unset { path: path.clone() },
+                            "$rename" => match value.as_str() {
+                                Some(to) => FieldChange::Rename {
+                                    from: path.clone(),
+                                    to: to.into(),
+                                },
+                                None => FieldChange::Other {
+                                    operator: operator.clone(),
+                                    path: path.clone(),
+                                    value: value.clone(),
+                                },
+                            },
+                            _ => FieldChange::Other {
+                                operator: operator.clone(),
+                                path: path.clone(),
+                                value: value.clone(),
+                            },
+                        });
+                    }
+                }
+
+                Some(changes)
+            }
+            UpdateModification::Diff {
+                ref set,
+                ref unset,
+                ref insert,
+            } => Some(
+                set.iter()
+                    .chain(insert)
+                    .map(|(path, value)| FieldChange::Set {
+                        path: path.clone(),
+                        value: value.clone(),
+                    })
+                    .chain(
+                        unset
+                            .iter()
+                            .map(|path| FieldChange::Unset { path: path.clone() }),
+                    )
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the fields set by update operators or a diff and their new values.
+    ///
+    /// This is the argument of `$set` or the fields updated and inserted by a `$v: 2` diff (with
+    /// nested sub-diffs flattened into dotted paths), which is empty if the update sets no fields.
+    /// Replacements return `None`.
+    pub fn set_fields(&self) -> Option<Document> {
+        match *self {
+            UpdateModification::Replacement(_) => None,
+            UpdateModification::Operators(ref operators) => {
+                Some(operators.get_document("$set").cloned().unwrap_or_default())
+            }
+            UpdateModification::Diff {
+                ref set,
+                ref insert,
+                ..
+            } => {
+                let mut fields = set.clone();
+                fields.extend(insert.clone());
+
+                Some(fields)
+            }
+        }
+    }
+
+    /// Returns the paths of the fields removed by `$unset` or a diff.
+    ///
+    /// This is empty if the update removes no fields. Replacements return `None`.
+    pub fn unset_fields(&self) -> Option<Vec<&str>> {
+        match *self {
+            UpdateModification::Replacement(_) => None,
+            UpdateModification::Operators(ref operators) => Some(
+                operators
+                    .get_document("$unset")
+                    .map(|fields| fields.keys().map(String::as_str).collect())
+                    .unwrap_or_default(),
+            ),
+            UpdateModification::Diff { ref unset, .. } => {
+                Some(unset.iter().map(String::as_str).collect())
+            }
+        }
+    }
+
+    /// Try to interpret the `o` field of an update oplog entry.
+    pub(crate) fn new(update: &Document) -> Result<UpdateModification> {
+        UpdateModification::with_server_version(update, None)
+    }
+
+    /// Try to interpret the `o` field of an update oplog entry written by the given server
+    /// version, if known.
+    ///
+    /// Servers predating MongoDB 5.0 never write diffs so a `$v: 2` field is then taken as an
+    /// operator rather than the version of a diff.
+    pub(crate) fn with_server_version(
+        update: &Document,
+        server_version: Option<ServerVersion>,
+    ) -> Result<UpdateModification> {
+        let diffs = server_version.is_none_or(|version| version.supports_update_diffs());
+
+        if diffs && matches!(update.get_i32("$v"), Ok(2)) {
+            let diff = update.get_document("diff")?;
+            let mut set = Document::new();
+            let mut unset = Vec::new();
+            let mut insert = Document::new();
+
+            parse_diff(diff, None, &mut set, &mut unset, &mut insert)?;
+
+            return Ok(UpdateModification::Diff { set, unset, insert });
+        }
+
+        if update.keys().any(|key| key.starts_with('$')) {
+            Ok(UpdateModification::Operators(update.to_owned()))
+        } else {
+            Ok(UpdateModification::Replacement(update.to_owned()))
+        }
+    }
+}
+
+/// Flatten a (possibly nested) `$v: 2` diff into dotted field paths.
+///
+/// Sub-diffs of embedded documents are stored under `s<field>` and array diffs are marked with
+/// `a: true`, storing updated elements under `u<index>` and sub-diffs under `s<index>`.
+fn parse_diff(
+    diff: &Document,
+    prefix: Option<&str>,
+    set: &mut Document,
+    unset: &mut Vec<String>,
+    insert: &mut Document,
+) -> Result<()> {
+    for (key, value) in diff {
+        match key.as_str() {
+            "u" => {
+                for (field, value) in as_document(value)? {
+                    set.insert(path(prefix, field), value.clone());
+                }
+            }
+            "i" => {
+                for (field, value) in as_document(value)? {
+                    insert.insert(path(prefix, field), value.clone());
+                }
+            }
+            "d" => {
+                for field in as_document(value)?.keys() {
+                    unset.push(path(prefix, field));
+                }
+            }
+            // The array marker and new array length carry no field changes of their own.
+            "a" | "l" => {}
+            key if key.starts_with('s') => {
+                let field = path(prefix, &key[1..]);
+
+                parse_diff(as_document(value)?, Some(&field), set, unset, insert)?;
+            }
+            key if key.starts_with('u') && key[1..].parse::<usize>().is_ok() => {
+                set.insert(path(prefix, &key[1..]), value.clone());
+            }
+            _ => return Err(Error::InvalidOperation),
+        }
+    }
+
+    Ok(())
+}
+
+fn as_document(bson: &Bson) -> Result<&Document> {
+    bson.as_document().ok_or(Error::InvalidOperation)
+}
+
+fn path(prefix: Option<&str>, field: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}.{}", prefix, field),
+        None => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn update_modification_detects_replacements() {
+        let modification = UpdateModification::new(&doc! { "_id" : 1, "foo" : "bar" }).unwrap();
+
+        assert_eq!(
+            modification,
+            UpdateModification::Replacement(doc! { "_id" : 1, "foo" : "bar" })
+        );
+    }
+
+    #[test]
+    fn update_modification_detects_legacy_operators() {
+        let modification =
+            UpdateModification::new(&doc! { "$v" : 1, "$set" : { "foo" : "baz" } }).unwrap();
+
+        assert_eq!(
+            modification,
+            UpdateModification::Operators(doc! { "$v" : 1, "$set" : { "foo" : "baz" } })
+        );
+    }
+
+    #[test]
+    fn update_modification_parses_diffs() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 2,
+            "diff" : {
+                "u" : { "status" : "shipped" },
+                "d" : { "legacy" : false },
+                "i" : { "tracking" : "abc" }
+            }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification,
+            UpdateModification::Diff {
+                set: doc! { "status" : "shipped" },
+                unset: vec!["legacy".to_string()],
+                insert: doc! { "tracking" : "abc" },
+            }
+        );
+    }
+
+    #[test]
+    fn update_modification_flattens_nested_diffs() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 2,
+            "diff" : {
+                "sp" : {
+                    "u" : { "x" : 1 },
+                    "sq" : { "i" : { "y" : 2 } }
+                },
+                "su" : { "d" : { "z" : false } }
+            }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification,
+            UpdateModification::Diff {
+                set: doc! { "p.x" : 1 },
+                unset: vec!["u.z".to_string()],
+                insert: doc! { "p.q.y" : 2 },
+            }
+        );
+    }
+
+    #[test]
+    fn update_modification_flattens_array_diffs() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 2,
+            "diff" : {
+                "stags" : {
+                    "a" : true,
+                    "u1" : "blue",
+                    "s2" : { "u" : { "name" : "red" } }
+                }
+            }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification,
+            UpdateModification::Diff {
+                set: doc! { "tags.1" : "blue", "tags.2.name" : "red" },
+                unset: vec![],
+                insert: doc! {},
+            }
+        );
+    }
+
+    #[test]
+    fn field_changes_sets_nested_fields() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 1,
+            "$set" : { "address.city" : "Leeds", "items.1.qty" : 2 }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification.field_changes(),
+            Some(vec![
+                FieldChange::Set {
+                    path: "address.city".into(),
+                    value: "Leeds".into(),
+                },
+                FieldChange::Set {
+                    path: "items.1.qty".into(),
+                    value: 2.into(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn field_changes_unsets_and_renames_fields() {
+        let modification = UpdateModification::new(&doc! {
+            "$unset" : { "legacy" : true },
+            "$rename" : { "nmae" : "name" },
+            "$inc" : { "count" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification.field_changes(),
+            Some(vec![
+                FieldChange::Unset {
+                    path: "legacy".into(),
+                },
+                FieldChange::Rename {
+                    from: "nmae".into(),
+                    to: "name".into(),
+                },
+                FieldChange::Other {
+                    operator: "$inc".into(),
+                    path: "count".into(),
+                    value: 1.into(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn field_changes_unifies_diffs() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 2,
+            "diff" : { "u" : { "status" : "shipped" }, "d" : { "legacy" : false } }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification.field_changes(),
+            Some(vec![
+                FieldChange::Set {
+                    path: "status".into(),
+                    value: "shipped".into(),
+                },
+                FieldChange::Unset {
+                    path: "legacy".into(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn set_and_unset_fields_of_operators() {
+        let modification = UpdateModification::new(&doc! {
+            "$set" : { "a" : 1, "b.c" : 2 },
+            "$unset" : { "d" : 1 },
+            "$inc" : { "e" : 1 }
+        })
+        .unwrap();
+
+        assert_eq!(modification.set_fields(), Some(doc! { "a" : 1, "b.c" : 2 }));
+        assert_eq!(modification.unset_fields(), Some(vec!["d"]));
+
+        let modification = UpdateModification::new(&doc! { "$inc" : { "e" : 1 } }).unwrap();
+
+        assert_eq!(modification.set_fields(), Some(Document::new()));
+        assert_eq!(modification.unset_fields(), Some(vec![]));
+    }
+
+    #[test]
+    fn set_and_unset_fields_of_diffs() {
+        let modification = UpdateModification::new(&doc! {
+            "$v" : 2,
+            "diff" : {
+                "u" : { "a" : 1 },
+                "i" : { "f" : true },
+                "d" : { "d" : false },
+                "sb" : { "u" : { "c" : 2 } }
+            }
+        })
+        .unwrap();
+
+        assert_eq!(
+            modification.set_fields(),
+            Some(doc! { "a" : 1, "b.c" : 2, "f" : true })
+        );
+        assert_eq!(modification.unset_fields(), Some(vec!["d"]));
+    }
+
+    #[test]
+    fn field_changes_is_none_for_replacements() {
+        let modification = UpdateModification::new(&doc! { "_id" : 1 }).unwrap();
+
+        assert_eq!(modification.field_changes(), None);
+    }
+
+    #[test]
+    fn update_modification_rejects_malformed_diffs() {
+        let modification = UpdateModification::new(&doc! { "$v" : 2, "diff" : { "u" : 1 } });
+
+        match modification {
+            Err(Error::InvalidOperation) => {}
+            _ => panic!("Expected invalid operation."),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! The version module is responsible for detecting the version of the connected server so oplog
+//! entries whose format changed between releases can be parsed accordingly.
+
+use std::fmt;
+
+use bson::doc;
+use mongodb::{bson, Client};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// The version of a MongoDB server, e.g. 6.0.4.
+///
+/// Versions are ordered by their major, minor and patch numbers so they can be compared against
+/// the release introducing a change, and can be given to `ParseOptions::server_version`.
+///
+/// # Example
+///
+/// ```
+/// use oplog::ServerVersion;
+///
+/// let version = ServerVersion::parse("4.4.18").unwrap();
+///
+/// assert!(version < ServerVersion::new(5, 0, 0));
+/// assert!(!version.supports_update_diffs());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServerVersion {
+    /// The major version, e.g. 6 for 6.0.4.
+    pub major: u32,
+    /// The minor version, e.g. 0 for 6.0.4.
+    pub minor: u32,
+    /// The patch version, e.g. 4 for 6.0.4.
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    /// Returns the given version.
+    pub fn new(major: u32, minor: u32, patch: u32) -> ServerVersion {
+        ServerVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Try to parse a version string as reported by `buildInfo`, e.g. `6.0.4` or `7.0.0-rc1`.
+    ///
+    /// Any pre-release suffix is ignored and a missing patch version is taken as 0. Returns `None`
+    /// if the string does not start with a version number.
+    pub fn parse(version: &str) -> Option<ServerVersion> {
+        let release = version.split(['-', '+']).next()?;
+        let mut numbers = release.split('.').map(str::parse::<u32>);
+
+        let major = numbers.next()?.ok()?;
+        let minor = numbers.next()?.ok()?;
+        let patch = match numbers.next() {
+            Some(patch) => patch.ok()?,
+            None => 0,
+        };
+
+        Some(ServerVersion::new(major, minor, patch))
+    }
+
+    /// Returns whether the server may record updates as `$v: 2` diffs, as MongoDB 5.0 and later
+    /// do.
+    pub fn supports_update_diffs(&self) -> bool {
+        *self >= ServerVersion::new(5, 0, 0)
+    }
+
+    /// Returns the version of the server the given client is connected to, as reported by the
+    /// `buildInfo` command.
+    ///
+    /// Returns `None` if the server reports a version which cannot be parsed.
+    pub(crate) async fn detect(client: &Client) -> Result<Option<ServerVersion>> {
+        let info = client
+            .database("admin")
+            .run_command(doc! { "buildInfo": 1 }, None)
+            .await?;
+
+        Ok(info.get_str("version").ok().and_then(ServerVersion::parse))
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_version_parses_release_versions() {
+        assert_eq!(
+            ServerVersion::parse("6.0.4"),
+            Some(ServerVersion::new(6, 0, 4))
+        );
+        assert_eq!(
+            ServerVersion::parse("3.6"),
+            Some(ServerVersion::new(3, 6, 0))
+        );
+    }
+
+    #[test]
+    fn server_version_ignores_pre_release_suffixes() {
+        assert_eq!(
+            ServerVersion::parse("7.0.0-rc1"),
+            Some(ServerVersion::new(7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn server_version_rejects_invalid_versions() {
+        assert_eq!(ServerVersion::parse("unknown"), None);
+        assert_eq!(ServerVersion::parse("6"), None);
+    }
+
+    #[test]
+    fn server_version_supports_update_diffs_from_5_0() {
+        assert!(!ServerVersion::new(4, 4, 18).supports_update_diffs());
+        assert!(ServerVersion::new(5, 0, 0).supports_update_diffs());
+        assert!(ServerVersion::new(7, 0, 2).supports_update_diffs());
+    }
+}
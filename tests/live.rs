@@ -0,0 +1,451 @@
+//! Integration tests against a live MongoDB replica set.
+//!
+//! These only run with the `live-mongo` feature enabled and connect to the replica set at
+//! `MONGO_URL` (or `mongodb://localhost` by default).
+#![cfg(feature = "live-mongo")]
+
+use futures::StreamExt;
+use mongodb::bson::{self, doc, Document};
+use mongodb::options::{ClientOptions, CreateCollectionOptions, FindOneOptions, ReadPreference};
+use mongodb::Client;
+use oplog::{IdempotentApplier, NamespaceFilter, OpType, Operation, Oplog};
+
+async fn client() -> Client {
+    let uri = std::env::var("MONGO_URL").unwrap_or_else(|_| "mongodb://localhost".to_string());
+
+    Client::with_uri_str(&uri).await.unwrap()
+}
+
+async fn last_timestamp(client: &Client) -> bson::Timestamp {
+    let coll = client.database("local").collection::<Document>("oplog.rs");
+    let opts = FindOneOptions::builder()
+        .sort(doc! { "$natural": -1 })
+        .build();
+
+    coll.find_one(None, opts)
+        .await
+        .unwrap()
+        .unwrap()
+        .get_timestamp("ts")
+        .unwrap()
+}
+
+fn inserted(operation: Operation) -> Document {
+    match operation {
+        Operation::Insert { document, .. } => document,
+        operation => panic!("Expected insert, got {}", operation),
+    }
+}
+
+#[tokio::test]
+async fn oplog_resumes_from_start_at() {
+    let client = client().await;
+    let coll = client
+        .database("oplog_test")
+        .collection::<Document>("start_at");
+
+    coll.insert_one(doc! { "n": 1 }, None).await.unwrap();
+    let ts = last_timestamp(&client).await;
+    coll.insert_one(doc! { "n": 2 }, None).await.unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .filter(doc! { "ns": "oplog_test.start_at" })
+        .build(&client)
+        .await
+        .unwrap();
+
+    let first = inserted(oplog.next().await.unwrap().unwrap());
+    let second = inserted(oplog.next().await.unwrap().unwrap());
+
+    assert_eq!(first.get_i32("n"), Ok(1));
+    assert_eq!(second.get_i32("n"), Ok(2));
+}
+
+#[tokio::test]
+async fn oplog_connects_with_client_options() {
+    let uri = std::env::var("MONGO_URL").unwrap_or_else(|_| "mongodb://localhost".to_string());
+    let options = ClientOptions::parse(&uri).await.unwrap();
+
+    let mut oplog = Oplog::with_options(options).await.unwrap();
+
+    assert!(oplog.next().await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn current_timestamp_is_at_least_the_last_write() {
+    let client = client().await;
+    let coll = client
+        .database("oplog_test")
+        .collection::<Document>("current_timestamp");
+
+    coll.insert_one(doc! { "n": 1 }, None).await.unwrap();
+    let written = last_timestamp(&client).await;
+
+    assert!(Oplog::current_timestamp(&client).await.unwrap() >= written);
+}
+
+#[tokio::test]
+async fn earliest_timestamp_is_at_most_the_current_timestamp() {
+    let client = client().await;
+
+    let earliest = Oplog::earliest_timestamp(&client).await.unwrap();
+    assert!(earliest <= Oplog::current_timestamp(&client).await.unwrap());
+}
+
+#[tokio::test]
+async fn oplog_tails_secondaries() {
+    let client = client().await;
+    let coll = client
+        .database("oplog_test")
+        .collection::<Document>("read_preference");
+    let ts = last_timestamp(&client).await;
+
+    coll.insert_one(doc! { "n": 1 }, None).await.unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter("oplog_test.read_preference")
+        .read_preference(ReadPreference::Secondary {
+            options: Default::default(),
+        })
+        .build(&client)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        inserted(oplog.next().await.unwrap().unwrap()).get_i32("n"),
+        Ok(1)
+    );
+}
+
+#[tokio::test]
+async fn oplog_only_yields_matching_namespaces() {
+    let client = client().await;
+    let db = client.database("oplog_test");
+    let ts = last_timestamp(&client).await;
+
+    db.collection::<Document>("ignored")
+        .insert_one(doc! { "n": 1 }, None)
+        .await
+        .unwrap();
+    db.collection::<Document>("namespace_filter")
+        .insert_one(doc! { "n": 2 }, None)
+        .await
+        .unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter("oplog_test.namespace_filter")
+        .build(&client)
+        .await
+        .unwrap();
+
+    let operation = oplog.next().await.unwrap().unwrap();
+
+    assert_eq!(operation.namespace(), Some("oplog_test.namespace_filter"));
+    assert_eq!(inserted(operation).get_i32("n"), Ok(2));
+}
+
+#[tokio::test]
+async fn oplog_for_database_only_yields_the_database() {
+    let client = client().await;
+    let n = last_timestamp(&client).await.time as i64;
+
+    client
+        .database("oplog_test")
+        .collection::<Document>("ignored")
+        .insert_one(doc! { "n": n }, None)
+        .await
+        .unwrap();
+    client
+        .database("oplog_for_database")
+        .collection::<Document>("for_database")
+        .insert_one(doc! { "n": n }, None)
+        .await
+        .unwrap();
+
+    let mut oplog = Oplog::for_database(&client, "oplog_for_database")
+        .await
+        .unwrap();
+
+    loop {
+        let operation = oplog.next().await.unwrap().unwrap();
+
+        assert_eq!(operation.database(), Some("oplog_for_database"));
+        if let Operation::Insert { ref document, .. } = operation {
+            if document.get_i64("n") == Ok(n) {
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn oplog_only_yields_namespaces_matching_regular_expressions() {
+    let client = client().await;
+    let db = client.database("oplog_test");
+    let ts = last_timestamp(&client).await;
+
+    db.collection::<Document>("unsold")
+        .insert_one(doc! { "n": 1 }, None)
+        .await
+        .unwrap();
+    db.collection::<Document>("sales_regex")
+        .insert_one(doc! { "n": 2 }, None)
+        .await
+        .unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter(NamespaceFilter::allow().regex("^oplog_test\\.sales_"))
+        .build(&client)
+        .await
+        .unwrap();
+
+    let operation = oplog.next().await.unwrap().unwrap();
+
+    assert_eq!(operation.namespace(), Some("oplog_test.sales_regex"));
+}
+
+#[tokio::test]
+async fn oplog_only_yields_requested_op_types() {
+    let client = client().await;
+    let coll = client
+        .database("oplog_test")
+        .collection::<Document>("op_types");
+    let ts = last_timestamp(&client).await;
+
+    coll.insert_one(doc! { "_id": 1 }, None).await.unwrap();
+    coll.update_one(doc! { "_id": 1 }, doc! { "$set": { "n": 1 } }, None)
+        .await
+        .unwrap();
+    coll.delete_one(doc! { "_id": 1 }, None).await.unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter("oplog_test.op_types")
+        .op_types(&[OpType::Insert, OpType::Delete])
+        .build(&client)
+        .await
+        .unwrap();
+
+    assert_eq!(oplog.next().await.unwrap().unwrap().op_char(), "i");
+    assert_eq!(oplog.next().await.unwrap().unwrap().op_char(), "d");
+}
+
+#[tokio::test]
+async fn operations_apply_to_another_database() {
+    let client = client().await;
+    let source = client
+        .database("oplog_test")
+        .collection::<Document>("apply_to");
+    let target = client.database("oplog_test_replica");
+    let ts = last_timestamp(&client).await;
+
+    source
+        .insert_one(doc! { "_id": 1, "n": 1 }, None)
+        .await
+        .unwrap();
+    source.delete_one(doc! { "_id": 1 }, None).await.unwrap();
+
+    let mut oplog = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter("oplog_test.apply_to")
+        .build(&client)
+        .await
+        .unwrap();
+    let replica = target.collection::<Document>("apply_to");
+
+    oplog
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .apply_to(&target)
+        .await
+        .unwrap();
+    assert_eq!(
+        replica.find_one(doc! { "_id": 1 }, None).await.unwrap(),
+        Some(doc! { "_id": 1, "n": 1 })
+    );
+
+    oplog
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .apply_to(&target)
+        .await
+        .unwrap();
+    assert_eq!(
+        replica.find_one(doc! { "_id": 1 }, None).await.unwrap(),
+        None
+    );
+}
+
+/// Returns the number of committed writes to the given namespace since the given timestamp,
+/// including those within transactions.
+async fn committed_writes(client: &Client, ts: bson::Timestamp, namespace: &str) -> usize {
+    let mut entries = client
+        .database("local")
+        .collection::<Document>("oplog.rs")
+        .find(doc! { "ts": { "$gt": ts } }, None)
+        .await
+        .unwrap();
+    let mut writes = 0;
+
+    while let Some(entry) = entries.next().await {
+        let operation = Operation::new(&entry.unwrap()).unwrap();
+        writes += operation
+            .flatten()
+            .iter()
+            .filter(|operation| operation.namespace() == Some(namespace))
+            .count();
+    }
+
+    writes
+}
+
+#[tokio::test]
+async fn idempotent_applier_never_applies_operations_twice() {
+    let client = client().await;
+    let source = client
+        .database("oplog_test")
+        .collection::<Document>("idempotent");
+    let target = client.database("oplog_test_idempotent");
+    target.drop(None).await.unwrap();
+    target.create_collection("idempotent", None).await.unwrap();
+    let ts = last_timestamp(&client).await;
+
+    source
+        .insert_one(doc! { "_id": 1, "n": 0 }, None)
+        .await
+        .unwrap();
+    source
+        .update_one(doc! { "_id": 1 }, doc! { "$inc": { "n": 1 } }, None)
+        .await
+        .unwrap();
+
+    let builder = Oplog::builder()
+        .start_at(ts)
+        .namespace_filter("oplog_test.idempotent");
+    let applier = IdempotentApplier::new(&client, "oplog_test_idempotent");
+    let mut oplog = applier
+        .resume(builder.clone())
+        .await
+        .unwrap()
+        .build(&client)
+        .await
+        .unwrap();
+
+    let insert = oplog.next().await.unwrap().unwrap();
+    assert!(applier.apply(&insert).await.unwrap());
+
+    // Simulate a crash after applying the update but before its progress is committed.
+    let update = oplog.next().await.unwrap().unwrap();
+    let mut session = client.start_session(None).await.unwrap();
+    session.start_transaction(None).await.unwrap();
+    update
+        .apply_to_with_session(&target, &mut session)
+        .await
+        .unwrap();
+    // The server aborts the transaction of a crashed client once its session expires.
+    session.abort_transaction().await.unwrap();
+    drop(oplog);
+
+    // Restart from the last recorded progress: the insert is read again but only the update is
+    // applied.
+    let restart = || async {
+        let applier = IdempotentApplier::new(&client, "oplog_test_idempotent");
+        let oplog = applier
+            .resume(builder.clone())
+            .await
+            .unwrap()
+            .build(&client)
+            .await
+            .unwrap();
+
+        (applier, oplog)
+    };
+    let (applier, mut oplog) = restart().await;
+    assert!(!applier
+        .apply(&oplog.next().await.unwrap().unwrap())
+        .await
+        .unwrap());
+    assert!(applier
+        .apply(&oplog.next().await.unwrap().unwrap())
+        .await
+        .unwrap());
+    drop(oplog);
+
+    // Restart again after the update was committed: it is read again but not applied.
+    let (applier, mut oplog) = restart().await;
+    let operation = oplog.next().await.unwrap().unwrap();
+    assert_eq!(operation.oplog_ts(), update.oplog_ts());
+    assert!(!applier.apply(&operation).await.unwrap());
+
+    assert_eq!(
+        target
+            .collection::<Document>("idempotent")
+            .find_one(doc! { "_id": 1 }, None)
+            .await
+            .unwrap(),
+        Some(doc! { "_id": 1, "n": 1 })
+    );
+    // Replaying an update as a `$set` leaves the same document so count the writes committed to
+    // the target instead: exactly one insert and one update.
+    assert_eq!(
+        committed_writes(&client, ts, "oplog_test_idempotent.idempotent").await,
+        2
+    );
+}
+
+#[tokio::test]
+async fn oplog_tails_a_named_oplog_collection() {
+    let client = client().await;
+    let local = client.database("local");
+    let _ = local.collection::<Document>("oplog.test").drop(None).await;
+    local
+        .create_collection(
+            "oplog.test",
+            CreateCollectionOptions::builder()
+                .capped(true)
+                .size(1024 * 1024)
+                .build(),
+        )
+        .await
+        .unwrap();
+    local
+        .collection::<Document>("oplog.test")
+        .insert_one(
+            doc! {
+                "ts": bson::Timestamp { time: 1479561394, increment: 0 },
+                "op": "i",
+                "ns": "foo.bar",
+                "o": { "_id": 1, "n": 1 }
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+    let mut oplog = Oplog::builder()
+        .oplog_collection("oplog.test")
+        .build(&client)
+        .await
+        .unwrap();
+
+    let document = inserted(oplog.next().await.unwrap().unwrap());
+
+    assert_eq!(document.get_i32("n"), Ok(1));
+}
+
+#[tokio::test]
+async fn oplog_detects_the_server_version() {
+    let client = client().await;
+
+    let oplog = Oplog::new(&client).await.unwrap();
+
+    assert!(oplog.server_version().is_some());
+}